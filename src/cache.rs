@@ -0,0 +1,87 @@
+use crate::chat_info::ChatInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+const CACHE_DIR: &str = "icq";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CachedChat {
+    pub chat_info: Option<ChatInfo>,
+}
+
+/// Chat metadata kept across restarts, stored as JSON in the purple user directory.
+///
+/// The history info isn't kept: it describes the messages a conversation displays, and a new
+/// conversation displays none.
+#[derive(Debug, Default)]
+pub struct ChatCache {
+    path: Option<PathBuf>,
+    chats: HashMap<String, CachedChat>,
+}
+
+impl ChatCache {
+    pub fn load(account_name: &str) -> Self {
+        let file_name = account_name.replace(|c: char| !c.is_ascii_alphanumeric(), "_") + ".json";
+        let path = PathBuf::from(purple::user_dir())
+            .join(CACHE_DIR)
+            .join(file_name);
+
+        let chats = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|error| {
+                log::error!("Failed to parse chat cache {:?}: {}", path, error);
+                HashMap::new()
+            }),
+            Err(error) => {
+                log::info!("No chat cache loaded from {:?}: {}", path, error);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            chats,
+        }
+    }
+
+    pub fn get(&self, sn: &str) -> Option<&CachedChat> {
+        self.chats.get(sn)
+    }
+
+    pub fn set_chat_info(&mut self, sn: &str, chat_info: ChatInfo) {
+        let cached = self.chats.entry(sn.into()).or_default();
+        if cached.chat_info.as_ref() == Some(&chat_info) {
+            return;
+        }
+        cached.chat_info = Some(chat_info);
+        self.save();
+    }
+
+    pub fn remove(&mut self, sn: &str) {
+        if self.chats.remove(sn).is_some() {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        // Write to a temporary file first so a crash never leaves a truncated cache.
+        let result = serde_json::to_string(&self.chats)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                let tmp_path = path.with_extension("json.tmp");
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&tmp_path, content).map_err(|e| e.to_string())?;
+                std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+            });
+        if let Err(error) = result {
+            log::error!("Failed to save chat cache {:?}: {}", path, error);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use super::icq;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use std::ffi::CString;
 
 lazy_static! {
@@ -9,9 +10,14 @@ lazy_static! {
     pub static ref TITLE: CString = CString::new("title").unwrap();
     pub static ref GROUP: CString = CString::new("group").unwrap();
     pub static ref STATE: CString = CString::new("state").unwrap();
+    pub static ref STAMP_NAME: CString = CString::new("Stamp").unwrap();
+    pub static ref MEMBERS_COUNT: CString = CString::new("members_count").unwrap();
+    pub static ref MEMBERS_COUNT_NAME: CString = CString::new("Members").unwrap();
+    pub static ref ABOUT: CString = CString::new("about").unwrap();
+    pub static ref ABOUT_NAME: CString = CString::new("About").unwrap();
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MemberRole(String);
 
 #[derive(Debug, Clone, Default)]
@@ -21,19 +27,23 @@ pub struct PartialChatInfo {
     pub group: Option<String>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ChatInfo {
     pub stamp: Option<String>,
     pub group: Option<String>,
     pub sn: String,
     pub title: String,
     pub about: Option<String>,
+    pub rules: Option<String>,
+    pub public: bool,
+    pub controlled: bool,
+    pub you_pending: bool,
     pub members_version: String,
     pub info_version: String,
     pub members: Vec<ChatMember>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChatMember {
     pub sn: String,
     pub friendly_name: Option<String>,
@@ -43,6 +53,20 @@ pub struct ChatMember {
     pub last_name: Option<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct PublicChatInfo {
+    pub stamp: String,
+    pub title: String,
+    pub about: Option<String>,
+    pub members_count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TopicChange {
+    pub author_sn: String,
+    pub time: i64,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChatInfoVersion {
     pub members_version: String,
@@ -103,6 +127,10 @@ impl From<icq::client::GetChatInfoResponseData> for ChatInfo {
             members_version: info.members_version,
             info_version: info.info_version,
             about: info.about,
+            rules: info.rules,
+            public: info.public,
+            controlled: info.controlled,
+            you_pending: info.you_pending,
             members: info
                 .members
                 .into_iter()
@@ -123,6 +151,17 @@ impl From<icq::client::GetChatInfoResponseData> for ChatInfo {
     }
 }
 
+impl From<icq::client::SearchChatsResponseChat> for PublicChatInfo {
+    fn from(chat: icq::client::SearchChatsResponseChat) -> Self {
+        Self {
+            stamp: chat.stamp,
+            title: chat.name,
+            about: chat.about,
+            members_count: chat.members_count,
+        }
+    }
+}
+
 impl From<icq::client::events::HistDlgStateMChatState> for ChatInfoVersion {
     fn from(info: icq::client::events::HistDlgStateMChatState) -> Self {
         Self {
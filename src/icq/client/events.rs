@@ -109,6 +109,24 @@ pub struct Buddy {
     pub user_type: UserType,
     pub official: Option<u32>,
     pub chat_type: Option<String>,
+    pub mute: Option<serde_json::Value>,
+    pub quiet: Option<serde_json::Value>,
+}
+
+impl Buddy {
+    /// Whether notifications from this buddy or chat are muted on the server.
+    pub fn is_muted(&self) -> bool {
+        is_set(&self.mute) || is_set(&self.quiet)
+    }
+}
+
+// Flags are sent either as booleans or as numbers.
+fn is_set(value: &Option<serde_json::Value>) -> bool {
+    match value {
+        Some(serde_json::Value::Bool(value)) => *value,
+        Some(serde_json::Value::Number(value)) => value.as_i64() != Some(0),
+        _ => false,
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -124,7 +142,22 @@ pub enum UserType {
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct PermitDenyData {}
+pub struct PermitDenyData {
+    // Example:
+    // {
+    //      'pdMode': 'denySome',
+    //      'allows': [],
+    //      'blocks': [],
+    //      'ignores': ['111111111']
+    // }
+    pub pd_mode: Option<String>,
+    #[serde(default)]
+    pub allows: Vec<String>,
+    #[serde(default)]
+    pub blocks: Vec<String>,
+    #[serde(default)]
+    pub ignores: Vec<String>,
+}
 
 // Event: MyInfo
 
@@ -250,6 +283,40 @@ pub struct HistDlgStateMessageChat {
     // pub live: Option<bool>
     pub sender: String, // The sender's sn
     pub name: String,   // The chat name
+    pub event: Option<HistDlgStateMessageChatEvent>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistDlgStateMessageChatEvent {
+    // Example (members added):
+    // {
+    //      'type': 'mchat',
+    //      'subtype': 'add_members',
+    //      'members': ['111111111']
+    // }
+    //
+    // Example (chat modified):
+    // {
+    //      'type': 'modified',
+    //      'modifiedInfo': {
+    //          'about': 'the new topic'
+    //      }
+    // }
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub subtype: Option<String>,
+    #[serde(default)]
+    pub members: Vec<String>,
+    pub modified_info: Option<HistDlgStateMessageChatEventModifiedInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HistDlgStateMessageChatEventModifiedInfo {
+    pub name: Option<String>,
+    pub about: Option<String>,
+    pub rules: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use surf::Body;
 
 pub mod events;
@@ -7,13 +8,36 @@ pub mod try_result;
 const SEND_CODE_URL: &str = "https://u.icq.net/api/v14/rapi/auth/sendCode";
 const LOGIN_WITH_PHONE_NUMBER_URL: &str =
     "https://u.icq.net/api/v14/smsreg/loginWithPhoneNumber.php";
+const CLIENT_LOGIN_URL: &str = "https://u.icq.net/api/v14/wim/auth/clientLogin";
 const START_SESSION_URL: &str = "https://u.icq.net/api/v14/wim/aim/startSession?";
+const END_SESSION_URL: &str = "https://u.icq.net/api/v14/wim/aim/endSession";
+const GET_PRESENCE_URL: &str = "https://u.icq.net/api/v14/wim/presence/get";
 const SEND_IM_URL: &str = "https://u.icq.net/api/v14/wim/im/sendIM";
+const ADD_BUDDY_URL: &str = "https://u.icq.net/api/v14/wim/buddylist/addBuddy";
+const REMOVE_BUDDY_URL: &str = "https://u.icq.net/api/v14/wim/buddylist/removeBuddy";
+const SET_BUDDY_ATTRIBUTE_URL: &str = "https://u.icq.net/api/v14/wim/buddylist/setBuddyAttribute";
+const MOVE_BUDDY_URL: &str = "https://u.icq.net/api/v14/wim/buddylist/moveBuddy";
+const SET_PERMIT_DENY_URL: &str = "https://u.icq.net/api/v14/wim/preference/setPermitDeny";
 const GET_CHAT_INFO_URL: &str = "https://u.icq.net/api/v14/rapi/getChatInfo";
 const GET_CHAT_HISTORY_URL: &str = "https://u.icq.net/api/v14/rapi/getHistory";
 const JOIN_CHAT_URL: &str = "https://u.icq.net/api/v14/rapi/joinChat";
+const LEAVE_CHAT_URL: &str = "https://u.icq.net/api/v14/rapi/leaveChat";
+const MOD_CHAT_URL: &str = "https://u.icq.net/api/v14/rapi/modChat";
+const ADD_CHAT_MEMBERS_URL: &str = "https://u.icq.net/api/v14/rapi/addChatMembers";
+const SEARCH_CHATS_URL: &str = "https://u.icq.net/api/v14/rapi/searchChats";
+const RESOLVE_PENDING_URL: &str = "https://u.icq.net/api/v14/rapi/resolvePending";
+const SEARCH_MESSAGES_URL: &str = "https://u.icq.net/api/v14/rapi/searchMessages";
 const FILES_INFO_URL: &str = "https://u.icq.net/api/v14/files/info";
 
+/// RAPI status code of a `sendCode` that must be retried with a captcha.
+const RAPI_CAPTCHA_REQUIRED: u32 = 40300;
+
+// Form fields whose values must not be written to the log.
+const SECRET_FORM_FIELDS: &[&str] = &["pwd"];
+
+// Added to the long poll timeout, after which the connection is considered hung.
+const FETCH_EVENTS_TIMEOUT_MARGIN: Duration = Duration::from_secs(10);
+
 #[derive(Debug)]
 pub enum Error {
     JsonSerializationError(surf::Error),
@@ -22,6 +46,9 @@ pub enum Error {
     RequestError(surf::Error),
     UrlParseError(url::ParseError),
     RapiError(u32, String),
+    WimError(u32, String),
+    TimeoutError(async_std::future::TimeoutError),
+    CaptchaRequired(SendCodeCaptcha),
 }
 type Result<T> = std::result::Result<T, Error>;
 
@@ -46,6 +73,10 @@ pub struct SendCodeBodyParams<'a> {
     pub route: &'a str,
     pub dev_id: &'a str,
     pub application: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captcha: Option<&'a str>,
 }
 
 #[derive(Serialize, Debug)]
@@ -61,10 +92,14 @@ pub struct SendCodeResponseResults {
     pub code_length: i32,
     pub session_id: String,
 }
+pub type SendCodeResponse = RapiResponse<SendCodeResponseResults>;
+
+/// The captcha to solve before a code is sent, given along with `RAPI_CAPTCHA_REQUIRED`.
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct SendCodeResponse {
-    pub results: SendCodeResponseResults,
+pub struct SendCodeCaptcha {
+    pub captcha_id: String,
+    pub captcha_url: String,
 }
 
 #[derive(Serialize, Debug)]
@@ -110,6 +145,12 @@ impl<T> RapiResponse<T> {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct RapiStatusResponse {
+    #[serde(default)]
+    pub status: RapiStatus,
+}
+
 #[derive(Deserialize, Debug, Default)]
 pub struct RapiStatus {
     #[serde(default)]
@@ -131,6 +172,32 @@ pub struct WebIcqData<T> {
     pub data: T,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct WimStatusResponse {
+    pub response: WimStatus,
+}
+
+impl WimStatusResponse {
+    pub fn into_result(self) -> Result<()> {
+        if self.response.status_code == 200 {
+            Ok(())
+        } else {
+            Err(Error::WimError(
+                self.response.status_code,
+                self.response.status_text,
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WimStatus {
+    pub status_code: u32,
+    #[serde(default)]
+    pub status_text: String,
+}
+
 type LoginWithPhoneNumberResponse = WebIcqResponse<LoginWithPhoneNumberResponseData>;
 
 #[derive(Deserialize, Debug)]
@@ -146,6 +213,28 @@ pub struct LoginWithPhoneNumberResponseToken {
     pub a: String,
 }
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientLoginBody<'a> {
+    pub s: &'a str,
+    pub pwd: &'a str,
+    pub id_type: &'a str,
+    pub client_name: &'a str,
+    pub k: &'a str,
+    pub f: &'a str,
+    pub r: &'a str,
+}
+
+type ClientLoginResponse = WebIcqResponse<ClientLoginResponseData>;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientLoginResponseData {
+    pub token: LoginWithPhoneNumberResponseToken,
+    pub host_time: u32,
+    pub session_secret: String,
+}
+
 type FetchEventsResponse = WebIcqResponse<FetchEventsResponseData>;
 
 #[derive(Deserialize, Debug)]
@@ -222,9 +311,13 @@ pub struct GetChatInfoResponseData {
     pub name: String,
     pub stamp: String,
     //pub create_time: usize,
-    //pub public: bool,
+    #[serde(default)]
+    pub public: bool,
     //pub live: bool,
-    //pub controlled: bool,
+    #[serde(default)]
+    pub controlled: bool,
+    #[serde(default)]
+    pub you_pending: bool,
     //pub members_count: usize,
     //pub admins_count: usize,
     //pub default_role: String,
@@ -233,6 +326,7 @@ pub struct GetChatInfoResponseData {
     pub info_version: String,
     pub sn: String,
     pub about: Option<String>,
+    pub rules: Option<String>,
     //pub abuse_reports_current_count: usize,
     pub members: Vec<ChatInfoResponseMember>,
 }
@@ -266,6 +360,123 @@ pub type JoinChatBody<'a> = RapiBody<'a, StampBodyParams<'a>>;
 
 pub type JoinChatResponse = RapiResponse<EmptyResponse>;
 
+#[derive(Serialize, Debug)]
+pub struct SnBodyParams<'a> {
+    pub sn: &'a str,
+}
+
+pub type LeaveChatBody<'a> = RapiBody<'a, SnBodyParams<'a>>;
+
+pub type LeaveChatResponse = RapiResponse<EmptyResponse>;
+
+pub type ModChatBody<'a> = RapiBody<'a, ModChatBodyParams<'a>>;
+
+#[derive(Serialize, Debug)]
+pub struct ModChatBodyParams<'a> {
+    pub sn: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub about: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<&'a str>,
+}
+
+pub type ModChatResponse = RapiResponse<EmptyResponse>;
+
+pub type AddChatMembersBody<'a> = RapiBody<'a, AddChatMembersBodyParams<'a>>;
+
+#[derive(Serialize, Debug)]
+pub struct AddChatMembersBodyParams<'a> {
+    pub sn: &'a str,
+    pub members: Vec<SnBodyParams<'a>>,
+}
+
+pub type AddChatMembersResponse = RapiResponse<EmptyResponse>;
+
+pub type SearchChatsBody<'a> = RapiBody<'a, SearchChatsBodyParams<'a>>;
+
+#[derive(Serialize, Debug)]
+pub struct SearchChatsBodyParams<'a> {
+    pub keyword: &'a str,
+    pub count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchChatsResponseData {
+    #[serde(default)]
+    pub chats: Vec<SearchChatsResponseChat>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchChatsResponseChat {
+    // Example:
+    // {
+    //      'sn': '111111111@chat.agent',
+    //      'stamp': 'AoLFnXSdUfTnETuNLrI',
+    //      'name': 'name of the chat',
+    //      'about': 'about the chat',
+    //      'membersCount': 42
+    // }
+    pub sn: String,
+    pub stamp: String,
+    pub name: String,
+    pub about: Option<String>,
+    #[serde(default)]
+    pub members_count: u32,
+}
+
+pub type SearchChatsResponse = RapiResponse<SearchChatsResponseData>;
+
+pub type SearchMessagesBody<'a> = RapiBody<'a, SearchMessagesBodyParams<'a>>;
+
+#[derive(Serialize, Debug)]
+pub struct SearchMessagesBodyParams<'a> {
+    pub keyword: &'a str,
+    /// Restricts the search to one chat, or searches every chat when absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sn: Option<&'a str>,
+    pub count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchMessagesResponseData {
+    #[serde(default)]
+    pub results: Vec<SearchMessagesResponseResult>,
+    #[serde(default)]
+    pub persons: Vec<events::HistDlgStatePerson>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchMessagesResponseResult {
+    // Example:
+    // {
+    //      'sn': '111111111@chat.agent',
+    //      'message': {
+    //          'msgId': '6852767962512184049',
+    //          'time': 1595534375,
+    //          'text': 'this is the text',
+    //          'chat': { 'sender': '111111111' }
+    //      }
+    // }
+    pub sn: String,
+    pub message: events::HistDlgStateMessage,
+}
+
+pub type SearchMessagesResponse = RapiResponse<SearchMessagesResponseData>;
+
+pub type ResolvePendingBody<'a> = RapiBody<'a, ResolvePendingBodyParams<'a>>;
+
+#[derive(Serialize, Debug)]
+pub struct ResolvePendingBodyParams<'a> {
+    pub sn: &'a str,
+    pub approve: bool,
+    pub members: Vec<SnBodyParams<'a>>,
+}
+
+pub type ResolvePendingResponse = RapiResponse<EmptyResponse>;
+
 #[derive(Serialize, Debug)]
 pub struct SendIMBody<'a> {
     pub t: &'a str,
@@ -288,6 +499,82 @@ pub struct SendIMResponseData {
 
 pub type SendIMResponse = WebIcqResponse<SendIMResponseData>;
 
+#[derive(Serialize, Debug)]
+pub struct GetPresenceBody<'a> {
+    /// Set to "1" to get the whole buddy list.
+    pub bl: &'a str,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+pub type GetPresenceResponse = WebIcqResponse<events::BuddyListData>;
+
+#[derive(Serialize, Debug)]
+pub struct EndSessionBody<'a> {
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AddBuddyBody<'a> {
+    pub buddy: &'a str,
+    pub group: &'a str,
+    pub pre_authorized: &'a str,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoveBuddyBody<'a> {
+    pub buddy: &'a str,
+    pub all_groups: &'a str,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SetBuddyAttributeBody<'a> {
+    pub buddy: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<i64>,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveBuddyBody<'a> {
+    pub buddy: &'a str,
+    pub group: &'a str,
+    pub new_group: &'a str,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SetPermitDenyBody<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pd_ignore: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pd_ignore_remove: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pd_block_remove: Option<&'a str>,
+    pub r: &'a str,
+    pub f: &'a str,
+    pub aimsid: &'a str,
+}
+
 #[derive(Serialize, Debug)]
 pub struct FilesInfoBody<'a> {
     pub aimsid: &'a str,
@@ -347,29 +634,95 @@ pub struct GetHistoryResponseData {
 pub type GetHistoryResponse = RapiResponse<GetHistoryResponseData>;
 
 pub async fn send_code(body: &SendCodeBody<'_>) -> Result<SendCodeResponse> {
-    post_json(SEND_CODE_URL, body).await
+    let response: serde_json::Value = post_json(SEND_CODE_URL, body).await?;
+    let status = serde_json::from_value::<RapiStatusResponse>(response.clone())
+        .map_err(Error::DeserializationError)?
+        .status;
+    if status.code == RAPI_CAPTCHA_REQUIRED {
+        let captcha = serde_json::from_value::<RapiResponse<SendCodeCaptcha>>(response)
+            .map_err(Error::DeserializationError)?
+            .results;
+        return Err(Error::CaptchaRequired(captcha));
+    }
+    rapi_response(response)
 }
 
 pub async fn login_with_phone_number(
     body: &LoginWithPhoneNumberBody<'_>,
 ) -> Result<LoginWithPhoneNumberResponse> {
-    post_form(LOGIN_WITH_PHONE_NUMBER_URL, body).await
+    let response: serde_json::Value = post_form(LOGIN_WITH_PHONE_NUMBER_URL, body).await?;
+    wim_response(response)
+}
+
+pub async fn get_captcha_image(url: &str) -> Result<Vec<u8>> {
+    log::debug!("GET {}", url);
+    let mut res = surf::get(url)
+        .with_default_headers()
+        .await
+        .map_err(Error::RequestError)?;
+    let body = res.body_bytes().await;
+    log::debug!("GET {} -> {}", url, res.status());
+    body.map_err(Error::RequestError)
+}
+
+pub async fn client_login(body: &ClientLoginBody<'_>) -> Result<ClientLoginResponse> {
+    let response: serde_json::Value = post_form(CLIENT_LOGIN_URL, body).await?;
+    wim_response(response)
 }
 
 pub async fn start_session(body: &StartSessionBody<'_>) -> Result<StartSessionResponse> {
     let params = serde_urlencoded::to_string(body).map_err(Error::UrlEncodedSerializationError)?;
     let url = START_SESSION_URL.to_string() + &params;
-    post_form(&url, &StartSessionFormBody {}).await
+    let response: serde_json::Value = post_form(&url, &StartSessionFormBody {}).await?;
+    wim_response(response)
+}
+
+pub async fn get_presence(body: &GetPresenceBody<'_>) -> Result<GetPresenceResponse> {
+    let params = serde_urlencoded::to_string(body).map_err(Error::UrlEncodedSerializationError)?;
+    let url = format!("{}?{}", GET_PRESENCE_URL, params);
+    get_wim(&url).await
+}
+
+pub async fn end_session(body: &EndSessionBody<'_>) -> Result<WimStatusResponse> {
+    post_form(END_SESSION_URL, body).await
 }
 
 pub async fn send_im(body: &SendIMBody<'_>) -> Result<SendIMResponse> {
     post_form(&SEND_IM_URL, body).await
 }
 
-pub async fn fetch_events(fetch_base_url: &str) -> Result<FetchEventsResponse> {
-    let url = url::Url::parse_with_params(fetch_base_url, &[("timeout", "30000")])
-        .map_err(Error::UrlParseError)?;
-    get_json(&url.to_string()).await
+pub async fn add_buddy(body: &AddBuddyBody<'_>) -> Result<WimStatusResponse> {
+    post_form(ADD_BUDDY_URL, body).await
+}
+
+pub async fn remove_buddy(body: &RemoveBuddyBody<'_>) -> Result<WimStatusResponse> {
+    post_form(REMOVE_BUDDY_URL, body).await
+}
+
+pub async fn set_buddy_attribute(body: &SetBuddyAttributeBody<'_>) -> Result<WimStatusResponse> {
+    post_form(SET_BUDDY_ATTRIBUTE_URL, body).await
+}
+
+pub async fn move_buddy(body: &MoveBuddyBody<'_>) -> Result<WimStatusResponse> {
+    post_form(MOVE_BUDDY_URL, body).await
+}
+
+pub async fn set_permit_deny(body: &SetPermitDenyBody<'_>) -> Result<WimStatusResponse> {
+    post_form(SET_PERMIT_DENY_URL, body).await
+}
+
+pub async fn fetch_events(fetch_base_url: &str, timeout: Duration) -> Result<FetchEventsResponse> {
+    let url = url::Url::parse_with_params(
+        fetch_base_url,
+        &[("timeout", timeout.as_millis().to_string())],
+    )
+    .map_err(Error::UrlParseError)?;
+    async_std::future::timeout(
+        timeout + FETCH_EVENTS_TIMEOUT_MARGIN,
+        get_wim(&url.to_string()),
+    )
+    .await
+    .map_err(Error::TimeoutError)?
 }
 
 pub async fn get_chat_info(body: &GetChatInfoBody<'_>) -> Result<GetChatInfoResponse> {
@@ -384,6 +737,30 @@ pub async fn join_chat(body: &JoinChatBody<'_>) -> Result<JoinChatResponse> {
     post_json(JOIN_CHAT_URL, body).await
 }
 
+pub async fn leave_chat(body: &LeaveChatBody<'_>) -> Result<LeaveChatResponse> {
+    post_json(LEAVE_CHAT_URL, body).await
+}
+
+pub async fn mod_chat(body: &ModChatBody<'_>) -> Result<ModChatResponse> {
+    post_json(MOD_CHAT_URL, body).await
+}
+
+pub async fn add_chat_members(body: &AddChatMembersBody<'_>) -> Result<AddChatMembersResponse> {
+    post_json(ADD_CHAT_MEMBERS_URL, body).await
+}
+
+pub async fn search_chats(body: &SearchChatsBody<'_>) -> Result<SearchChatsResponse> {
+    post_json(SEARCH_CHATS_URL, body).await
+}
+
+pub async fn search_messages(body: &SearchMessagesBody<'_>) -> Result<SearchMessagesResponse> {
+    post_json(SEARCH_MESSAGES_URL, body).await
+}
+
+pub async fn resolve_pending(body: &ResolvePendingBody<'_>) -> Result<ResolvePendingResponse> {
+    post_json(RESOLVE_PENDING_URL, body).await
+}
+
 pub async fn files_info(file_id: &str, body: &FilesInfoBody<'_>) -> Result<FilesInfoResponse> {
     let params = serde_urlencoded::to_string(body).map_err(Error::UrlEncodedSerializationError)?;
     let url = format!("{}/{}?{}", FILES_INFO_URL, file_id, params);
@@ -405,15 +782,39 @@ async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T> {
     serde_json::from_str(&body).map_err(Error::DeserializationError)
 }
 
+async fn get_wim<T: serde::de::DeserializeOwned>(url: &str) -> Result<WebIcqResponse<T>> {
+    let response: serde_json::Value = get_json(url).await?;
+    wim_response(response)
+}
+
+// Failed WIM responses have a status code but no data, so the status is checked first.
+fn wim_response<T: serde::de::DeserializeOwned>(
+    response: serde_json::Value,
+) -> Result<WebIcqResponse<T>> {
+    serde_json::from_value::<WimStatusResponse>(response.clone())
+        .map_err(Error::DeserializationError)?
+        .into_result()?;
+    serde_json::from_value(response).map_err(Error::DeserializationError)
+}
+
+// Like WIM, failed RAPI responses come without results.
+fn rapi_response<T: serde::de::DeserializeOwned>(
+    response: serde_json::Value,
+) -> Result<RapiResponse<T>> {
+    let status = serde_json::from_value::<RapiStatusResponse>(response.clone())
+        .map_err(Error::DeserializationError)?
+        .status;
+    if status.code >= 40000 {
+        return Err(Error::RapiError(status.code, status.reason));
+    }
+    serde_json::from_value(response).map_err(Error::DeserializationError)
+}
+
 async fn post_form<T: serde::Serialize, U: serde::de::DeserializeOwned>(
     url: &str,
     body: &T,
 ) -> Result<U> {
-    log::debug!(
-        "POST {} <- {}",
-        url,
-        serde_urlencoded::to_string(body).unwrap()
-    );
+    log::debug!("POST {} <- {}", url, loggable_form(body));
     let mut res = surf::post(url)
         .with_default_headers()
         .body(Body::from_form(body).map_err(Error::RequestError)?)
@@ -425,6 +826,20 @@ async fn post_form<T: serde::Serialize, U: serde::de::DeserializeOwned>(
     serde_json::from_str(&body).map_err(Error::DeserializationError)
 }
 
+// Encode a form body for the log, with the secret fields redacted.
+fn loggable_form<T: serde::Serialize>(body: &T) -> String {
+    let form = serde_urlencoded::to_string(body).unwrap_or_default();
+    form.split('&')
+        .map(|field| match field.split_once('=') {
+            Some((name, _)) if SECRET_FORM_FIELDS.contains(&name) => {
+                format!("{}=<redacted>", name)
+            }
+            _ => field.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
 async fn post_json<T: serde::Serialize, U: serde::de::DeserializeOwned>(
     url: &str,
     body: &T,
@@ -445,3 +860,25 @@ async fn post_json<T: serde::Serialize, U: serde::de::DeserializeOwned>(
     let body = body.map_err(Error::RequestError)?;
     serde_json::from_str(&body).map_err(Error::DeserializationError)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loggable_form_redacts_password() {
+        let body = ClientLoginBody {
+            s: "123456789",
+            pwd: "hunter2",
+            id_type: "ICQ",
+            client_name: "webicq",
+            k: "key",
+            f: "json",
+            r: "1",
+        };
+        let form = loggable_form(&body);
+        assert!(!form.contains("hunter2"));
+        assert!(form.contains("pwd=<redacted>"));
+        assert!(form.contains("s=123456789"));
+    }
+}
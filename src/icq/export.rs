@@ -0,0 +1,231 @@
+use super::client::events::{HistDlgStateMessage, HistDlgStatePerson};
+use super::history;
+use super::poller;
+use super::protocol::{self, SessionInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsonl" => Some(Self::JsonLines),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    ApiError(protocol::Error),
+    IoError(std::io::Error),
+    SerializationError(serde_json::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize, Debug)]
+struct ExportedMessage {
+    msg_id: String,
+    time: i64,
+    author_sn: String,
+    author_friendly: String,
+    text: String,
+    files: Vec<ExportedFile>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct ExportedFile {
+    file_id: String,
+    file_name: String,
+    mime: String,
+    file_size: usize,
+    md5: String,
+    url: String,
+}
+
+/// Write the history of a chat to `path`, oldest message first, and return the number of
+/// exported messages.
+///
+/// The file must not exist yet. Messages are written one page at a time, so that a long
+/// history isn't held in memory.
+pub async fn export_history(
+    session: &SessionInfo,
+    chat_sn: &str,
+    path: &Path,
+    format: ExportFormat,
+    since: Option<i64>,
+) -> Result<usize> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(Error::IoError)?;
+    let mut writer = BufWriter::new(file);
+    if format == ExportFormat::Csv {
+        writeln!(writer, "msg_id,time,author_sn,author_friendly,text,files")
+            .map_err(Error::IoError)?;
+    }
+
+    // A date is found by reading pages backwards, and the history is then read forwards from
+    // it. The whole history is read forwards from the start.
+    let mut from_msg_id = match since {
+        Some(since) => {
+            let oldest_msg_id = history::find_oldest_since(session, chat_sn, since)
+                .await
+                .map_err(Error::ApiError)?;
+            match oldest_msg_id {
+                Some(msg_id) => msg_id,
+                None => {
+                    writer.flush().map_err(Error::IoError)?;
+                    return Ok(0);
+                }
+            }
+        }
+        None => protocol::OLDEST_MSG_ID.to_string(),
+    };
+
+    let page_size = session.options.history_page_size as i32;
+    let mut files = HashMap::new();
+    let mut count = 0;
+    loop {
+        let page = protocol::get_history(session, chat_sn, &from_msg_id, page_size)
+            .await
+            .map_err(Error::ApiError)?;
+
+        // A page includes the message it starts from, which is only new on the first one.
+        let mut messages = page
+            .messages
+            .into_iter()
+            .filter(|m| count == 0 || m.msg_id != from_msg_id)
+            .collect::<Vec<_>>();
+        if messages.is_empty() {
+            break;
+        }
+        messages.sort_by_key(|m| m.time);
+        from_msg_id = messages.last().unwrap().msg_id.clone();
+
+        for message in &messages {
+            let exported =
+                export_message(session, chat_sn, &page.persons, message, &mut files).await;
+            write_message(&mut writer, format, &exported)?;
+        }
+        writer.flush().map_err(Error::IoError)?;
+        count += messages.len();
+    }
+
+    Ok(count)
+}
+
+fn write_message(
+    writer: &mut impl Write,
+    format: ExportFormat,
+    exported: &ExportedMessage,
+) -> Result<()> {
+    match format {
+        ExportFormat::JsonLines => {
+            let line = serde_json::to_string(exported).map_err(Error::SerializationError)?;
+            writeln!(writer, "{}", line).map_err(Error::IoError)
+        }
+        ExportFormat::Csv => {
+            let files =
+                serde_json::to_string(&exported.files).map_err(Error::SerializationError)?;
+            writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                csv_field(&exported.msg_id),
+                exported.time,
+                csv_field(&exported.author_sn),
+                csv_field(&exported.author_friendly),
+                csv_field(&exported.text),
+                csv_field(&files),
+            )
+            .map_err(Error::IoError)
+        }
+    }
+}
+
+async fn export_message(
+    session: &SessionInfo,
+    chat_sn: &str,
+    persons: &[HistDlgStatePerson],
+    message: &HistDlgStateMessage,
+    files: &mut HashMap<String, Option<ExportedFile>>,
+) -> ExportedMessage {
+    // See `poller::process_hist_dlg_state_messages` for how the author is found.
+    let author_sn = match &message.chat {
+        Some(chat) => chat.sender.clone(),
+        None => chat_sn.to_string(),
+    };
+    let author_friendly = poller::find_author_friendly(&author_sn, persons).to_string();
+    let text = message.text.clone().unwrap_or_default();
+
+    let mut message_files = Vec::new();
+    for (_, file_id) in poller::find_message_files(&text) {
+        if !files.contains_key(&file_id) {
+            let file = match protocol::files_info(session, &file_id).await {
+                Ok(file_info) => Some(ExportedFile {
+                    file_id: file_id.clone(),
+                    file_name: file_info.info.file_name,
+                    mime: file_info.info.mime,
+                    file_size: file_info.info.file_size,
+                    md5: file_info.info.md5,
+                    url: file_info.info.dlink,
+                }),
+                Err(error) => {
+                    log::error!("Failed to get file info {}: {:?}", file_id, error);
+                    None
+                }
+            };
+            files.insert(file_id.clone(), file);
+        }
+        if let Some(file) = &files[&file_id] {
+            message_files.push(file.clone());
+        }
+    }
+
+    ExportedMessage {
+        msg_id: message.msg_id.clone(),
+        time: message.time,
+        author_sn,
+        author_friendly,
+        text,
+        files: message_files,
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_field_plain() {
+        assert_eq!(csv_field("hello world"), "hello world");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_field_quoted() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+}
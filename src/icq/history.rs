@@ -0,0 +1,160 @@
+use super::client;
+use super::protocol::{self, SessionInfo};
+
+#[derive(Debug, Clone)]
+pub enum HistoryLimit {
+    /// The `n` most recent messages.
+    Count(usize),
+    /// Every message sent at or after this timestamp.
+    Since(i64),
+}
+
+/// Walk the history of a chat backwards, one page at a time, until `limit` is reached.
+///
+/// When no `from_msg_id` is given, starts from the newest message. Messages are returned
+/// from oldest to newest.
+pub async fn fetch_backwards(
+    session: &SessionInfo,
+    chat_sn: &str,
+    from_msg_id: Option<&str>,
+    limit: &HistoryLimit,
+) -> protocol::Result<client::GetHistoryResponseData> {
+    let page_size = session.options.history_page_size as i32;
+    let mut from_msg_id = from_msg_id.unwrap_or(protocol::LATEST_MSG_ID).to_string();
+    let mut messages = Vec::new();
+    let mut persons = Vec::new();
+
+    loop {
+        let page = protocol::get_history(session, chat_sn, &from_msg_id, -page_size).await?;
+        persons.extend(page.persons);
+
+        // A page includes the message it starts from.
+        let mut page_messages = page
+            .messages
+            .into_iter()
+            .filter(|m| m.msg_id != from_msg_id)
+            .collect::<Vec<_>>();
+        if page_messages.is_empty() {
+            break;
+        }
+        page_messages.sort_by_key(|m| m.time);
+        from_msg_id = page_messages[0].msg_id.clone();
+
+        let is_complete = match limit {
+            HistoryLimit::Count(count) => messages.len() + page_messages.len() >= *count,
+            HistoryLimit::Since(since) => page_messages[0].time < *since,
+        };
+        messages.extend(page_messages);
+        if is_complete {
+            break;
+        }
+    }
+
+    messages.sort_by_key(|m| m.time);
+    match limit {
+        HistoryLimit::Count(count) => {
+            let excess = messages.len().saturating_sub(*count);
+            messages.drain(..excess);
+        }
+        HistoryLimit::Since(since) => messages.retain(|m| m.time >= *since),
+    }
+
+    Ok(client::GetHistoryResponseData { messages, persons })
+}
+
+/// Walk the history of a chat backwards to the oldest message sent at or after `since`, and
+/// return its id. Unlike `fetch_backwards`, the messages aren't kept.
+pub async fn find_oldest_since(
+    session: &SessionInfo,
+    chat_sn: &str,
+    since: i64,
+) -> protocol::Result<Option<String>> {
+    let page_size = session.options.history_page_size as i32;
+    let mut from_msg_id = protocol::LATEST_MSG_ID.to_string();
+    let mut oldest_msg_id = None;
+
+    loop {
+        let page = protocol::get_history(session, chat_sn, &from_msg_id, -page_size).await?;
+
+        // A page includes the message it starts from.
+        let mut page_messages = page
+            .messages
+            .into_iter()
+            .filter(|m| m.msg_id != from_msg_id)
+            .collect::<Vec<_>>();
+        if page_messages.is_empty() {
+            break;
+        }
+        page_messages.sort_by_key(|m| m.time);
+        from_msg_id = page_messages[0].msg_id.clone();
+
+        if let Some(message) = page_messages.iter().find(|m| m.time >= since) {
+            oldest_msg_id = Some(message.msg_id.clone());
+        }
+        if page_messages[0].time < since {
+            break;
+        }
+    }
+
+    Ok(oldest_msg_id)
+}
+
+/// Walk the history of a chat forward from `from_msg_id`, excluded, up to the newest message
+/// or until `max_count` messages are read.
+///
+/// Messages are returned from oldest to newest.
+pub async fn fetch_forwards(
+    session: &SessionInfo,
+    chat_sn: &str,
+    from_msg_id: &str,
+    max_count: usize,
+) -> protocol::Result<client::GetHistoryResponseData> {
+    let page_size = session.options.history_page_size as i32;
+    let mut from_msg_id = from_msg_id.to_string();
+    let mut messages = Vec::new();
+    let mut persons = Vec::new();
+
+    while messages.len() < max_count {
+        let page = protocol::get_history(session, chat_sn, &from_msg_id, page_size).await?;
+        persons.extend(page.persons);
+
+        // A page includes the message it starts from.
+        let mut page_messages = page
+            .messages
+            .into_iter()
+            .filter(|m| m.msg_id != from_msg_id)
+            .collect::<Vec<_>>();
+        if page_messages.is_empty() {
+            break;
+        }
+        page_messages.sort_by_key(|m| m.time);
+        from_msg_id = page_messages.last().unwrap().msg_id.clone();
+        messages.extend(page_messages);
+    }
+
+    messages.truncate(max_count);
+    Ok(client::GetHistoryResponseData { messages, persons })
+}
+
+/// Read up to `count` messages on each side of `msg_id`, the message itself included.
+///
+/// Messages are returned from oldest to newest.
+pub async fn fetch_around(
+    session: &SessionInfo,
+    chat_sn: &str,
+    msg_id: &str,
+    count: i32,
+) -> protocol::Result<client::GetHistoryResponseData> {
+    let before = protocol::get_history(session, chat_sn, msg_id, -(count + 1)).await?;
+    let after = protocol::get_history(session, chat_sn, msg_id, count + 1).await?;
+
+    let mut persons = before.persons;
+    persons.extend(after.persons);
+
+    // Both pages include the message they start from.
+    let mut messages = before.messages;
+    messages.extend(after.messages.into_iter().filter(|m| m.msg_id != msg_id));
+    messages.sort_by_key(|m| m.time);
+
+    Ok(client::GetHistoryResponseData { messages, persons })
+}
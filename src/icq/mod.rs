@@ -1,4 +1,7 @@
 pub mod client;
-mod poller;
+pub mod export;
+pub mod history;
+pub mod poller;
 pub mod protocol;
 pub mod system;
+pub mod tasks;
@@ -4,32 +4,98 @@ use super::client::events::Event;
 use super::client::events::EventData;
 use super::client::try_result;
 use super::client::try_result::TryResult;
+use super::history;
 use super::protocol;
+use super::tasks::Tasks;
 use crate::icq::protocol::SessionInfo;
 use crate::logging;
 use crate::messages::{AccountInfo, FdSender, SystemMessage};
 use crate::Handle;
-use crate::MsgInfo;
-use crate::PartialChatInfo;
+use crate::{BuddyInfo, ChatInfo, PartialChatInfo, TopicChange};
+use crate::{MsgInfo, MsgSource};
 use futures::future;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+/// A running `fetch_events_loop` task.
+#[derive(Debug)]
+pub struct PollerHandle {
+    abort_handle: future::AbortHandle,
+    join_handle: async_std::task::JoinHandle<Result<(), future::Aborted>>,
+}
+
+impl PollerHandle {
+    pub fn spawn(tx: FdSender<SystemMessage>, account_info: AccountInfo) -> Self {
+        let (fetch_events_loop, abort_handle) =
+            future::abortable(fetch_events_loop(tx, account_info));
+        Self {
+            abort_handle,
+            join_handle: async_std::task::spawn_local(fetch_events_loop),
+        }
+    }
+
+    /// Cancel the pending request, if any, and wait until the loop has exited.
+    pub async fn stop(self) {
+        self.abort_handle.abort();
+        if self.join_handle.await.is_err() {
+            log::info!("Poller aborted");
+        }
+    }
+}
+
 static FILES_URL_PREFIX: &str = "https://files.icq.net/get/";
+const MAX_CATCH_UP_MESSAGES: usize = 1000;
+
+// Consecutive failures after which the connection is reported as broken, letting purple
+// reconnect the account.
+const MAX_FAILURES: u32 = 6;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// When and how long to poll, as told by the previous events response.
+struct FetchTiming {
+    /// How long the server may hold the request before answering.
+    timeout: Duration,
+    /// How long to wait before the next request.
+    delay: Duration,
+}
+
+impl FetchTiming {
+    /// Used until the server tells its own long poll timeout, which is capped at this one.
+    fn initial(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            delay: Duration::from_secs(0),
+        }
+    }
+}
+
+enum PollerState {
+    /// Fetching events from the fetch URL of the current session.
+    Polling {
+        fetch_base_url: String,
+        timing: FetchTiming,
+        failures: u32,
+    },
+    /// The session was rejected and a new one must be started.
+    Restarting { failures: u32 },
+}
 
 pub async fn fetch_events_loop(mut tx: FdSender<SystemMessage>, account_info: AccountInfo) {
-    let mut fetch_base_url = {
-        account_info
-            .protocol_data
-            .session
-            .read()
-            .await
-            .as_ref()
-            .unwrap()
-            .fetch_base_url
-            .clone()
+    let (fetch_base_url, fetch_timeout) = {
+        let session = account_info.protocol_data.session.read().await;
+        let session = session.as_ref().unwrap();
+        (
+            session.fetch_base_url.clone(),
+            session.options.fetch_timeout,
+        )
+    };
+    let mut state = PollerState::Polling {
+        fetch_base_url,
+        timing: FetchTiming::initial(fetch_timeout),
+        failures: 0,
     };
 
     while !account_info
@@ -37,41 +103,191 @@ pub async fn fetch_events_loop(mut tx: FdSender<SystemMessage>, account_info: Ac
         .session_closed
         .load(Ordering::Relaxed)
     {
-        // Skip if we are disconnected.
         if tx
             .account_proxy(&account_info.handle.clone())
             .is_disconnected()
             .await
         {
             log::info!("Account is disconnected. Not fetching events.");
-            async_std::task::sleep(Duration::from_secs(5)).await;
+            break;
         }
 
-        // Fetch Events
-        log::info!("Fetching events...");
+        let failures = match &state {
+            PollerState::Polling { failures, .. } => *failures,
+            PollerState::Restarting { failures } => *failures,
+        };
+        if failures >= MAX_FAILURES {
+            log::error!("Giving up after {} failures", failures);
+            tx.connection_proxy(&account_info.handle)
+                .error_reason(
+                    purple::PurpleConnectionError::PURPLE_CONNECTION_ERROR_NETWORK_ERROR,
+                    "Lost connection with the server".into(),
+                )
+                .await;
+            break;
+        }
+        if failures > 0 {
+            let backoff = MAX_BACKOFF.min(Duration::from_secs(1 << (failures - 1)));
+            log::info!("Retrying in {:?}", backoff);
+            async_std::task::sleep(backoff).await;
+        }
 
-        match protocol::fetch_events(&fetch_base_url).await {
-            Err(error) => {
-                log::error!("Failed to fetch events: {:?}", error);
-                async_std::task::sleep(Duration::from_secs(5)).await;
+        state = match state {
+            PollerState::Polling {
+                fetch_base_url,
+                timing,
+                failures,
+            } => {
+                if failures == 0 && timing.delay > Duration::from_secs(0) {
+                    async_std::task::sleep(timing.delay).await;
+                }
+                log::info!("Fetching events...");
+                match protocol::fetch_events(&fetch_base_url, timing.timeout).await {
+                    Ok(fetch_events_response_data) => {
+                        save_session(
+                            &mut tx,
+                            &account_info,
+                            &fetch_events_response_data.fetch_base_url,
+                            &fetch_events_response_data.ts,
+                        )
+                        .await;
+                        log::info!("Fetched Events: {:?}", fetch_events_response_data.events);
+                        process_events(
+                            tx.clone(),
+                            &account_info,
+                            fetch_events_response_data.events,
+                        )
+                        .await;
+                        PollerState::Polling {
+                            fetch_base_url: fetch_events_response_data.fetch_base_url,
+                            // The configured timeout caps the one the server asks for.
+                            timing: FetchTiming {
+                                timeout: fetch_timeout.min(Duration::from_millis(
+                                    fetch_events_response_data.fetch_timeout.into(),
+                                )),
+                                delay: Duration::from_millis(
+                                    fetch_events_response_data.time_to_next_fetch.into(),
+                                ),
+                            },
+                            failures: 0,
+                        }
+                    }
+                    Err(error) if error.is_session_rejected() => {
+                        log::warn!("Session rejected: {:?}", error);
+                        PollerState::Restarting { failures: 0 }
+                    }
+                    // The account stays connected while retrying, until giving up.
+                    Err(error) => {
+                        log::error!("Failed to fetch events: {:?}", error);
+                        PollerState::Polling {
+                            fetch_base_url,
+                            timing,
+                            failures: failures + 1,
+                        }
+                    }
+                }
             }
-            Ok(fetch_events_response_data) => {
-                log::info!("Fetched Events: {:?}", fetch_events_response_data.events);
-                process_events(tx.clone(), &account_info, fetch_events_response_data.events).await;
-                fetch_base_url = fetch_events_response_data.fetch_base_url;
+            PollerState::Restarting { failures } => {
+                log::info!("Starting a new session...");
+                tx.connection_proxy(&account_info.handle)
+                    .set_state(purple::PurpleConnectionState::PURPLE_CONNECTING)
+                    .await;
+                match restart_session(&account_info).await {
+                    Ok(fetch_base_url) => {
+                        let ts = protocol::timestamp().to_string();
+                        save_session(&mut tx, &account_info, &fetch_base_url, &ts).await;
+                        tx.connection_proxy(&account_info.handle)
+                            .set_state(purple::PurpleConnectionState::PURPLE_CONNECTED)
+                            .await;
+                        PollerState::Polling {
+                            fetch_base_url,
+                            timing: FetchTiming::initial(fetch_timeout),
+                            failures: 0,
+                        }
+                    }
+                    Err(error) => {
+                        log::error!("Failed to start session: {:?}", error);
+                        PollerState::Restarting {
+                            failures: failures + 1,
+                        }
+                    }
+                }
             }
-        }
+        };
         logging::flush();
     }
 }
 
+// Save where the event stream is at, so that it can be resumed after a restart.
+async fn save_session(
+    tx: &mut FdSender<SystemMessage>,
+    account_info: &AccountInfo,
+    fetch_base_url: &str,
+    ts: &str,
+) {
+    // Once the account is closed the saved session belongs to close, which may have cleared it.
+    if account_info
+        .protocol_data
+        .session_closed
+        .load(Ordering::Relaxed)
+    {
+        return;
+    }
+    let saved_session = {
+        let session = account_info.protocol_data.session.read().await;
+        let session = match session.as_ref() {
+            Some(session) => session,
+            None => return,
+        };
+        protocol::SavedSession {
+            fetch_base_url: fetch_base_url.to_string(),
+            ..protocol::SavedSession::new(session, ts.to_string())
+        }
+    };
+    if let Err(error) = tx
+        .account_proxy(&account_info.handle)
+        .set_settings(saved_session)
+        .await
+    {
+        log::error!("Failed to save session: {:?}", error);
+    }
+}
+
+// Replace the session of the account and return its fetch URL.
+async fn restart_session(account_info: &AccountInfo) -> protocol::Result<String> {
+    let (registration_data, options) = {
+        let session = account_info.protocol_data.session.read().await;
+        let session = session.as_ref().unwrap();
+        (session.registration_data.clone(), session.options.clone())
+    };
+    let session = protocol::start_session(&registration_data, &options).await?;
+    let fetch_base_url = session.fetch_base_url.clone();
+    (*account_info.protocol_data.session.write().await) = Some(session);
+    account_info
+        .protocol_data
+        .last_seq_num
+        .store(0, Ordering::Relaxed);
+
+    // The new session starts with a buddy list, which catches up on the messages sent while
+    // the previous one was down.
+    account_info
+        .protocol_data
+        .needs_catch_up
+        .store(true, Ordering::Relaxed);
+    Ok(fetch_base_url)
+}
+
 pub async fn process_events(
     tx: FdSender<SystemMessage>,
     account_info: &AccountInfo,
     events: Vec<TryResult<Event>>,
 ) {
+    let mut missed_events = false;
     for event in events {
         log::info!("Processing event: {:?}", event);
+        if let Some(seq_num) = event_seq_num(&event) {
+            missed_events |= !is_next_seq_num(account_info, seq_num);
+        }
 
         match event {
             try_result::TryResult(Ok(event)) => {
@@ -85,8 +301,8 @@ pub async fn process_events(
                     EventData::MyInfo(event_data) => {
                         process_event_my_info(&event_data).await;
                     }
-                    EventData::PermitDeny(_event_data) => {
-                        // TODO
+                    EventData::PermitDeny(event_data) => {
+                        process_event_permit_deny(tx.clone(), account_info, &event_data).await;
                     }
                     EventData::Presence(_event_data) => {
                         // TODO
@@ -112,6 +328,69 @@ pub async fn process_events(
             }
         }
     }
+
+    if missed_events {
+        resync(tx, account_info).await;
+    }
+}
+
+fn event_seq_num(event: &TryResult<Event>) -> Option<u32> {
+    match event {
+        try_result::TryResult(Ok(event)) => Some(event.seq_num),
+        try_result::TryResult(Err(unknown_event)) => unknown_event
+            .get("seqNum")
+            .and_then(|seq_num| seq_num.as_u64())
+            .map(|seq_num| seq_num as u32),
+    }
+}
+
+// Remember the last seen seqNum, and tell whether the events before this one were all seen.
+fn is_next_seq_num(account_info: &AccountInfo, seq_num: u32) -> bool {
+    let last_seq_num = account_info
+        .protocol_data
+        .last_seq_num
+        .swap(seq_num, Ordering::Relaxed);
+    if follows_seq_num(last_seq_num, seq_num) {
+        return true;
+    }
+    if seq_num <= last_seq_num {
+        log::warn!("Events reset from {} to {}", last_seq_num, seq_num);
+    } else {
+        log::warn!("Missed events {} to {}", last_seq_num + 1, seq_num - 1);
+    }
+    false
+}
+
+fn follows_seq_num(last_seq_num: u32, seq_num: u32) -> bool {
+    // A session starts from any seqNum.
+    last_seq_num == 0 || seq_num == last_seq_num.wrapping_add(1)
+}
+
+// Get back what the missed events would have told: the buddy list and the chat messages.
+pub async fn resync(tx: FdSender<SystemMessage>, account_info: &AccountInfo) {
+    log::info!("Resynchronizing");
+    let session = {
+        account_info
+            .protocol_data
+            .session
+            .read()
+            .await
+            .as_ref()
+            .unwrap()
+            .clone()
+    };
+    match protocol::get_buddy_list(&session).await {
+        Ok(buddy_list) => {
+            account_info
+                .protocol_data
+                .needs_catch_up
+                .store(true, Ordering::Relaxed);
+            process_event_buddy_list(tx, account_info, &buddy_list).await;
+        }
+        Err(error) => {
+            log::error!("Failed to get buddy list: {:?}", error);
+        }
+    }
 }
 
 pub async fn process_event_buddy_list(
@@ -119,6 +398,8 @@ pub async fn process_event_buddy_list(
     account_info: &AccountInfo,
     event_data: &events::BuddyListData,
 ) {
+    let mut chat_sns = Vec::new();
+    let mut buddies = Vec::new();
     for group in &event_data.groups {
         for buddy in &group.buddies {
             match &buddy.user_type {
@@ -131,20 +412,139 @@ pub async fn process_event_buddy_list(
                         },
                         group: Some(group.name.clone()),
                     };
+                    chat_sns.push(chat_info.sn.clone());
+                    let muted = buddy.is_muted();
                     tx.handle_proxy(&account_info.handle)
                         .exec_no_return(move |plugin, protocol_data| {
                             let connection = &mut protocol_data.connection;
                             plugin.chat_joined(connection, &chat_info);
+                            plugin.mute_changed(connection, &chat_info.sn, muted);
                         })
                         .await;
                 }
-                events::UserType::ICQ => {}
+                events::UserType::ICQ => {
+                    buddies.push(BuddyInfo {
+                        sn: buddy.aim_id.clone(),
+                        alias: buddy.friendly.clone(),
+                        group: group.name.clone(),
+                        muted: buddy.is_muted(),
+                    });
+                }
                 events::UserType::Unknown => {
                     log::error!("Got unknown user type!");
                 }
             }
         }
     }
+
+    tx.handle_proxy(&account_info.handle)
+        .exec_no_return(move |plugin, protocol_data| {
+            let connection = &mut protocol_data.connection;
+            plugin.buddy_list_loaded(connection, &buddies);
+        })
+        .await;
+
+    // The first buddy list after login tells which chats may have missed messages.
+    if account_info
+        .protocol_data
+        .needs_catch_up
+        .swap(false, Ordering::Relaxed)
+    {
+        catch_up_chats(tx, account_info, &chat_sns).await;
+    }
+}
+
+// Deliver the messages sent since the last delivered message of each chat. This runs before
+// the following events are processed, so that messages are shown in order.
+pub async fn catch_up_chats(
+    mut tx: FdSender<SystemMessage>,
+    account_info: &AccountInfo,
+    chat_sns: &[String],
+) {
+    let session = {
+        account_info
+            .protocol_data
+            .session
+            .read()
+            .await
+            .as_ref()
+            .unwrap()
+            .clone()
+    };
+
+    for chat_sn in chat_sns {
+        let sn = chat_sn.clone();
+        let last_message_id = tx
+            .handle_proxy(&account_info.handle)
+            .exec(move |plugin, protocol_data| {
+                plugin.last_message_id(&mut protocol_data.account, &sn)
+            })
+            .await
+            .flatten();
+        let last_message_id = match last_message_id {
+            Some(last_message_id) => last_message_id,
+            None => continue,
+        };
+
+        log::info!("Catching up {} from {}", chat_sn, last_message_id);
+        match history::fetch_forwards(&session, chat_sn, &last_message_id, MAX_CATCH_UP_MESSAGES)
+            .await
+        {
+            Ok(history) => {
+                if history.messages.is_empty() {
+                    continue;
+                }
+                process_hist_dlg_state_messages(
+                    tx.clone(),
+                    session.clone(),
+                    account_info.handle.clone(),
+                    &account_info.protocol_data.tasks,
+                    chat_sn,
+                    &history.persons,
+                    None,
+                    &history.messages,
+                    MsgSource::CatchUp,
+                )
+                .await;
+            }
+            Err(error) => {
+                log::error!("Failed to catch up {}: {:?}", chat_sn, error);
+            }
+        }
+    }
+}
+
+pub async fn process_event_permit_deny(
+    mut tx: FdSender<SystemMessage>,
+    account_info: &AccountInfo,
+    event_data: &events::PermitDenyData,
+) {
+    let privacy_type = match event_data.pd_mode.as_deref() {
+        Some("allowAll") => Some(purple::PurplePrivacyType::PURPLE_PRIVACY_ALLOW_ALL),
+        Some("denyAll") => Some(purple::PurplePrivacyType::PURPLE_PRIVACY_DENY_ALL),
+        Some("permitSome") => Some(purple::PurplePrivacyType::PURPLE_PRIVACY_ALLOW_USERS),
+        Some("denySome") => Some(purple::PurplePrivacyType::PURPLE_PRIVACY_DENY_USERS),
+        Some(pd_mode) => {
+            log::warn!("Unknown permit deny mode: {}", pd_mode);
+            None
+        }
+        None => None,
+    };
+    let permit = event_data.allows.clone();
+    // Ignored users are the ones blocked from the ICQ clients.
+    let deny = event_data
+        .blocks
+        .iter()
+        .chain(&event_data.ignores)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    tx.handle_proxy(&account_info.handle)
+        .exec_no_return(move |plugin, protocol_data| {
+            let connection = &mut protocol_data.connection;
+            plugin.privacy_lists_loaded(connection, privacy_type, &permit, &deny);
+        })
+        .await;
 }
 
 pub async fn process_event_my_info(_event_data: &events::MyInfoData) {
@@ -186,23 +586,61 @@ pub async fn process_event_hist_dlg_state(
         tx,
         session,
         account_info.handle.clone(),
+        &account_info.protocol_data.tasks,
         &event_data.sn,
         &event_data.persons,
         event_data.mchat_state.as_ref(),
         &event_data.messages,
+        MsgSource::Live,
     )
     .await;
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn process_hist_dlg_state_messages(
     mut tx: FdSender<SystemMessage>,
     session: SessionInfo,
     handle: Handle,
+    tasks: &Tasks,
     event_sn: &str,
     event_persons: &[events::HistDlgStatePerson],
     event_mchat_state: Option<&events::HistDlgStateMChatState>,
     hist_dlg_state_messages: &[events::HistDlgStateMessage],
+    source: MsgSource,
 ) {
+    // An invitation to a chat we are not a member of must not create the chat.
+    let inviter_sn = hist_dlg_state_messages.iter().rev().find_map(|message| {
+        let chat = message.chat.as_ref()?;
+        let event = chat.event.as_ref()?;
+        if event.subtype.as_deref() == Some("invite") && event.members.contains(&session.aim_id) {
+            Some(chat.sender.clone())
+        } else {
+            None
+        }
+    });
+    if let (MsgSource::Live, Some(inviter_sn)) = (source, inviter_sn) {
+        if process_chat_invite(tx.clone(), &session, &handle, event_sn, inviter_sn).await {
+            return;
+        }
+    }
+
+    // Neither must our own join request while it waits for an admin, join_chat tells the user.
+    let is_pending = hist_dlg_state_messages.iter().any(|message| {
+        message
+            .chat
+            .as_ref()
+            .and_then(|chat| chat.event.as_ref())
+            .map(|event| {
+                event.subtype.as_deref() == Some("waiting_for_approve")
+                    && event.members.contains(&session.aim_id)
+            })
+            .unwrap_or(false)
+    });
+    if source == MsgSource::Live && is_pending {
+        log::info!("Join request to {} is pending approval", event_sn);
+        return;
+    }
+
     // Create the chat if necessary
     let chat_sn = event_sn.to_string();
     let chat_friendly = find_author_friendly(&chat_sn, event_persons).to_string();
@@ -223,10 +661,6 @@ pub async fn process_hist_dlg_state_messages(
 
     // Create Chat Entries
     for message in hist_dlg_state_messages {
-        let message_text = match message.text.as_ref() {
-            Some(m) => m,
-            None => continue,
-        };
         // For group conversation:
         // - event_data.sn is the Group's sn
         // - message.chat.sender is the author's sn
@@ -240,6 +674,27 @@ pub async fn process_hist_dlg_state_messages(
                 None => event_sn.to_string(),
             }
         };
+
+        if let Some(event) = message.chat.as_ref().and_then(|chat| chat.event.as_ref()) {
+            process_message_chat_event(
+                &mut tx,
+                &session,
+                &handle,
+                tasks,
+                &chat_sn,
+                event_persons,
+                &author_sn,
+                message.time,
+                event,
+                source,
+            )
+            .await;
+        }
+
+        let message_text = match message.text.as_ref() {
+            Some(m) => m,
+            None => continue,
+        };
         let author_friendly = find_author_friendly(&author_sn, event_persons).to_string();
 
         let message_text = htmlescape::encode_minimal(&message_text);
@@ -252,6 +707,7 @@ pub async fn process_hist_dlg_state_messages(
             text: message_text.into_owned(),
             time: message.time,
             message_id: message.msg_id.clone(),
+            source,
         };
 
         tx.handle_proxy(&handle)
@@ -272,26 +728,153 @@ pub async fn process_hist_dlg_state_messages(
         .await;
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn process_message_chat_event(
+    tx: &mut FdSender<SystemMessage>,
+    session: &SessionInfo,
+    handle: &Handle,
+    tasks: &Tasks,
+    chat_sn: &str,
+    event_persons: &[events::HistDlgStatePerson],
+    author_sn: &str,
+    time: i64,
+    event: &events::HistDlgStateMessageChatEvent,
+    source: MsgSource,
+) {
+    if let Some(about) = event.modified_info.as_ref().and_then(|i| i.about.clone()) {
+        let chat_sn = chat_sn.to_string();
+        let change = TopicChange {
+            author_sn: author_sn.to_string(),
+            time,
+        };
+        tx.handle_proxy(handle)
+            .exec_no_return(move |plugin, protocol_data| {
+                let connection = &mut protocol_data.connection;
+                plugin.chat_topic_changed(connection, &chat_sn, change, &about);
+            })
+            .await;
+    }
+
+    let chat_title = find_author_friendly(chat_sn, event_persons);
+    // Requests and notifications only make sense for events as they happen.
+    if source != MsgSource::Live {
+        return;
+    }
+
+    match event.subtype.as_deref() {
+        Some("waiting_for_approve") => {
+            // Don't block the poller while the admin answers.
+            for member_sn in &event.members {
+                tasks.spawn(prompt_join_request(
+                    tx.clone(),
+                    session.clone(),
+                    handle.clone(),
+                    chat_sn.to_string(),
+                    chat_title.to_string(),
+                    member_sn.clone(),
+                    find_author_friendly(member_sn, event_persons).to_string(),
+                ));
+            }
+        }
+        Some("joining_rejected") if event.members.contains(&session.aim_id) => {
+            tx.connection_proxy(handle)
+                .notify_message(
+                    purple::PurpleNotifyMsgType::PURPLE_NOTIFY_MSG_INFO,
+                    "Join request rejected".into(),
+                    format!("Your request to join {} was rejected", chat_title),
+                    None,
+                )
+                .await;
+        }
+        _ => {}
+    }
+}
+
+async fn prompt_join_request(
+    mut tx: FdSender<SystemMessage>,
+    session: SessionInfo,
+    handle: Handle,
+    chat_sn: String,
+    chat_title: String,
+    member_sn: String,
+    member_friendly: String,
+) {
+    let action = tx
+        .account_proxy(&handle)
+        .request_action(
+            Some("Join request".into()),
+            Some(format!("{} wants to join {}", member_friendly, chat_title)),
+            Some(format!("User ID: {}", member_sn)),
+            0,
+            None,
+            vec!["Approve".into(), "Reject".into(), "Later".into()],
+        )
+        .await;
+    let approve = match action {
+        Some(0) => true,
+        Some(1) => false,
+        _ => return,
+    };
+
+    log::info!(
+        "Resolving join request: {} {} {}",
+        chat_sn,
+        member_sn,
+        approve
+    );
+    if let Err(error) = protocol::resolve_pending(&session, &chat_sn, &[&member_sn], approve).await
+    {
+        log::error!("Failed to resolve join request: {:?}", error);
+    }
+    logging::flush();
+}
+
+// Offer to join the chat the user was invited to. Returns false if already a member,
+// so the rest of the dialog state is processed as usual.
+async fn process_chat_invite(
+    mut tx: FdSender<SystemMessage>,
+    session: &SessionInfo,
+    handle: &Handle,
+    chat_sn: &str,
+    inviter_sn: String,
+) -> bool {
+    let sn = chat_sn.to_string();
+    let is_member = tx
+        .handle_proxy(handle)
+        .exec(move |_plugin, protocol_data| {
+            purple::Chat::find(&mut protocol_data.account, &sn).is_some()
+        })
+        .await
+        .unwrap_or(false);
+    if is_member {
+        log::info!("Ignoring invite to already joined chat {}", chat_sn);
+        return false;
+    }
+
+    let chat_info = match protocol::get_chat_info_by_sn(session, chat_sn).await {
+        Ok(chat_info_response) => ChatInfo::from(chat_info_response),
+        Err(error) => {
+            log::error!("Failed to get invited chat info: {:?}", error);
+            return true;
+        }
+    };
+
+    tx.handle_proxy(handle)
+        .exec_no_return(move |plugin, protocol_data| {
+            let connection = &mut protocol_data.connection;
+            plugin.chat_invited(connection, &chat_info, &inviter_sn);
+        })
+        .await;
+    true
+}
+
 // Clippy false positive, lifetimes are required for this code to compile.
 #[allow(clippy::needless_lifetimes)]
 pub async fn process_message_files<'a>(
     mut message: Cow<'a, str>,
     session: &protocol::SessionInfo,
 ) -> Cow<'a, str> {
-    // Extract files URL from message.
-    let message_files = message
-        .match_indices(FILES_URL_PREFIX)
-        .map(|(index, _)| {
-            let id_starts_at = index + FILES_URL_PREFIX.len();
-            let id_ends_at = message[id_starts_at..]
-                .find(' ')
-                .map(|i| id_starts_at + i)
-                .unwrap_or_else(|| message.len());
-            log::info!("{}..{}..{}", index, id_starts_at, id_ends_at);
-            let file_id = message[id_starts_at..id_ends_at].to_string();
-            (index..id_ends_at, file_id)
-        })
-        .collect::<Vec<_>>();
+    let message_files = find_message_files(&message);
 
     // Fetch all files info through ICQ API.
     let files_info_futures = message_files
@@ -317,6 +900,23 @@ pub async fn process_message_files<'a>(
     message
 }
 
+/// Extract the files URL from a message, with their position and file id.
+pub fn find_message_files(message: &str) -> Vec<(Range<usize>, String)> {
+    message
+        .match_indices(FILES_URL_PREFIX)
+        .map(|(index, _)| {
+            let id_starts_at = index + FILES_URL_PREFIX.len();
+            let id_ends_at = message[id_starts_at..]
+                .find(' ')
+                .map(|i| id_starts_at + i)
+                .unwrap_or_else(|| message.len());
+            log::info!("{}..{}..{}", index, id_starts_at, id_ends_at);
+            let file_id = message[id_starts_at..id_ends_at].to_string();
+            (index..id_ends_at, file_id)
+        })
+        .collect()
+}
+
 async fn fetch_file_info(
     session: &protocol::SessionInfo,
     file_id: String,
@@ -351,3 +951,22 @@ fn pretty_size(size: usize) -> String {
         (size / 1_000_000_000).to_string() + "gb"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_seq_num_in_order() {
+        assert!(follows_seq_num(0, 42));
+        assert!(follows_seq_num(41, 42));
+        assert!(follows_seq_num(u32::MAX, 0));
+    }
+
+    #[test]
+    fn follows_seq_num_gap_or_reset() {
+        assert!(!follows_seq_num(40, 42));
+        assert!(!follows_seq_num(42, 42));
+        assert!(!follows_seq_num(42, 1));
+    }
+}
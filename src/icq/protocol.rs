@@ -1,17 +1,42 @@
 use super::client;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::future::Future;
-use std::time::SystemTime;
-use uuid::Uuid;
+use std::time::{Duration, Instant, SystemTime};
 
-const LANGUAGE: &str = "en-US";
+const DEFAULT_LANGUAGE: &str = "en-US";
 const KEY: &str = "ic1rtwz1s1Hj1O0r";
-const LOCALE: &str = "en-US";
+const DEFAULT_LOCALE: &str = "en-US";
+const DEFAULT_MEMBER_LIMIT: u32 = 50;
+const DEFAULT_HISTORY_PAGE_SIZE: u32 = 50;
+// Used until the server tells its own long poll timeout.
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
 const CAPS: &str = "094613584C7F11D18222444553540000,0946135C4C7F11D18222444553540000,0946135b4c7f11d18222444553540000,0946135E4C7F11D18222444553540000,AABC2A1AF270424598B36993C6231952,1f99494e76cbc880215d6aeab8e42268";
 const EVENTS: &str = "myInfo,presence,buddylist,typing,hiddenChat,hist,mchat,sentIM,imState,dataIM,offlineIM,userAddedToBuddyList,service,lifestream,apps,permitDeny,diff,webrtcMsg";
+// Status codes of the registration endpoints.
+const RAPI_INVALID_PHONE_NUMBER: u32 = 40000;
+const RAPI_RATE_LIMITED: u32 = 42900;
+const WIM_INVALID_CREDENTIALS: u32 = 330;
+const WIM_INVALID_CODE: u32 = 400;
+const WIM_RATE_LIMITED: u32 = 430;
+// Status codes of the RAPI requests the user isn't allowed to make.
+const RAPI_FORBIDDEN: std::ops::Range<u32> = 40300..40400;
+// How long to wait before sending a new registration code.
+const RESEND_CODE_COOLDOWN: Duration = Duration::from_secs(60);
+// How long an unused session is kept by the server, in seconds.
+const DEFAULT_SESSION_TIMEOUT: u32 = 2_592_000;
 const PRESENCE_FIELDS: &str = "aimId,displayId,friendly,friendlyName,state,userType,statusMsg,statusTime,lastseen,ssl,mute,abContactName,abPhoneNumber,abPhones,official,quiet,autoAddition,largeIconId,nick,userState";
 
+/// Passed as `mute` to mute a buddy or a chat until it is unmuted.
+const MUTE_FOREVER: i64 = -1;
+
+/// Passed as `from_msg_id` to read history from the newest message.
+pub const LATEST_MSG_ID: &str = "-1";
+/// Passed as `from_msg_id` to read history forwards from the first message, as message ids
+/// only grow.
+pub const OLDEST_MSG_ID: &str = "0";
+
 pub type ChatInfo = client::GetChatInfoResponseData;
 pub type MsgInfo = client::SendIMResponseData;
 
@@ -19,24 +44,120 @@ pub type MsgInfo = client::SendIMResponseData;
 pub enum Error {
     ApiError(client::Error),
     MissingCode,
+    Registration(RegistrationError),
+    SessionExpired,
+}
+
+/// Why the server refused to register or log in the account.
+#[derive(Debug)]
+pub enum RegistrationError {
+    InvalidPhoneNumber,
+    InvalidCode,
+    InvalidPassword,
+    CaptchaRequired,
+    RateLimited,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RegistrationError::InvalidPhoneNumber => {
+                "The phone number is invalid, it must start with + and the country code"
+            }
+            RegistrationError::InvalidCode => "The code is wrong or has expired",
+            RegistrationError::InvalidPassword => "The ICQ number or the password is wrong",
+            RegistrationError::CaptchaRequired => "The captcha must be solved to get a code",
+            RegistrationError::RateLimited => {
+                "Too many attempts, wait a few minutes before trying again"
+            }
+        })
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Whether the server rejected the session, rather than the request failing to complete.
+    pub fn is_session_rejected(&self) -> bool {
+        matches!(self, Error::ApiError(client::Error::WimError(_, _)))
+    }
+
+    /// Whether the server rejected the auth token, which must then be renewed.
+    pub fn is_token_rejected(&self) -> bool {
+        matches!(
+            self,
+            Error::ApiError(client::Error::WimError(401, _))
+                | Error::ApiError(client::Error::WimError(403, _))
+        )
+    }
+
+    /// Whether the user lacks the rights for the request, such as changing a chat as a member.
+    pub fn is_permission_denied(&self) -> bool {
+        matches!(
+            self,
+            Error::ApiError(client::Error::RapiError(code, _)) if RAPI_FORBIDDEN.contains(code)
+        )
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
     pub registration_data: RegistrationData,
+    pub options: ProtocolOptions,
     pub aim_id: String,
     pub aim_sid: String,
     pub fetch_base_url: String,
+    /// When the session was started, as a unix timestamp.
+    pub started_at: u32,
+}
+
+/// Protocol tuning, set in the account options.
+#[derive(Debug, Clone)]
+pub struct ProtocolOptions {
+    pub language: String,
+    pub locale: String,
+    /// How many members are listed with the chat info.
+    pub member_limit: u32,
+    /// How many messages are read per history request.
+    pub history_page_size: u32,
+    /// The longest the server may hold an events request.
+    pub fetch_timeout: Duration,
+    /// How long an unused session is kept by the server, in seconds.
+    pub session_timeout: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl ProtocolOptions {
+    pub const LANGUAGE_OPTION_KEY: &'static str = "language";
+    pub const LOCALE_OPTION_KEY: &'static str = "locale";
+    pub const MEMBER_LIMIT_OPTION_KEY: &'static str = "member_limit";
+    pub const HISTORY_PAGE_SIZE_OPTION_KEY: &'static str = "history_page_size";
+    pub const FETCH_TIMEOUT_OPTION_KEY: &'static str = "fetch_timeout";
+    pub const SESSION_TIMEOUT_OPTION_KEY: &'static str = "session_timeout";
+}
+
+impl Default for ProtocolOptions {
+    fn default() -> Self {
+        Self {
+            language: DEFAULT_LANGUAGE.into(),
+            locale: DEFAULT_LOCALE.into(),
+            member_limit: DEFAULT_MEMBER_LIMIT,
+            history_page_size: DEFAULT_HISTORY_PAGE_SIZE,
+            fetch_timeout: DEFAULT_FETCH_TIMEOUT,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct RegistrationData {
     pub session_id: String,
     pub session_key: String,
     pub token: String,
     pub host_time: u32,
+    /// Identifies the account as a single device across sessions. It isn't written when empty,
+    /// so clearing the rest of the registration keeps it.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub device_id: String,
 }
 
 impl RegistrationData {
@@ -44,35 +165,256 @@ impl RegistrationData {
     pub const SESSION_KEY_SETTING_KEY: &'static str = "session_key";
     pub const TOKEN_SETTING_KEY: &'static str = "token";
     pub const HOST_TIME_SETTING_KEY: &'static str = "host_time";
+    pub const DEVICE_ID_SETTING_KEY: &'static str = "device_id";
+}
+
+/// What is needed to resume a session after a restart, saved in the account settings.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SavedSession {
+    pub aim_id: String,
+    pub aim_sid: String,
+    pub fetch_base_url: String,
+    /// Time of the last fetched events, as given by the server.
+    pub ts: String,
+    pub started_at: u32,
+}
+
+impl SavedSession {
+    pub const AIM_ID_SETTING_KEY: &'static str = "aim_id";
+    pub const AIM_SID_SETTING_KEY: &'static str = "aim_sid";
+    pub const FETCH_BASE_URL_SETTING_KEY: &'static str = "fetch_base_url";
+    pub const TS_SETTING_KEY: &'static str = "ts";
+    pub const STARTED_AT_SETTING_KEY: &'static str = "started_at";
+
+    pub fn new(session: &SessionInfo, ts: String) -> Self {
+        Self {
+            aim_id: session.aim_id.clone(),
+            aim_sid: session.aim_sid.clone(),
+            fetch_base_url: session.fetch_base_url.clone(),
+            ts,
+            started_at: session.started_at,
+        }
+    }
+
+    fn is_expired(&self, session_timeout: u32) -> bool {
+        match self.ts.parse::<u32>() {
+            Ok(ts) => timestamp().saturating_sub(ts) >= session_timeout,
+            Err(_) => true,
+        }
+    }
+}
+
+/// How the registration code is sent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeRoute {
+    Sms,
+    /// A voice call reading the code.
+    Voice,
+}
+
+impl CodeRoute {
+    fn as_str(self) -> &'static str {
+        match self {
+            CodeRoute::Sms => "sms",
+            CodeRoute::Voice => "ivr",
+        }
+    }
+}
+
+/// A registration code that was sent to the user.
+#[derive(Debug, Clone)]
+pub struct SentCode {
+    pub route: CodeRoute,
+    /// The number of digits of the code, if the server told.
+    pub code_length: Option<usize>,
+    /// When a new code can be asked for.
+    pub resend_at: Instant,
+    /// Why the last code entered was refused.
+    pub error: Option<String>,
+}
+
+impl SentCode {
+    /// Whether the code looks like the one that was sent, before asking the server.
+    pub fn accepts(&self, code: &str) -> bool {
+        let has_length = match self.code_length {
+            Some(code_length) => code.len() == code_length,
+            None => !code.is_empty(),
+        };
+        has_length && code.chars().all(|c| c.is_ascii_digit())
+    }
+}
+
+#[derive(Debug)]
+pub enum CodeReply {
+    Code(String),
+    Resend(CodeRoute),
+    Cancel,
+}
+
+/// Normalize a phone number to the E.164 format, such as `+33612345678`.
+///
+/// The number must include its country code, either after `+` or `00`. Without either it
+/// can't be told apart from a UIN.
+pub fn normalize_phone_number(phone_number: &str) -> Option<String> {
+    let phone_number = phone_number
+        .chars()
+        .filter(|c| !c.is_whitespace() && !"-.()".contains(*c))
+        .collect::<String>();
+    let digits = phone_number
+        .strip_prefix('+')
+        .or_else(|| phone_number.strip_prefix("00"))?;
+    let is_valid = (8..=15).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && !digits.starts_with('0');
+    if is_valid {
+        Some(format!("+{}", digits))
+    } else {
+        None
+    }
+}
+
+// Sort out the failures of the registration endpoints the user can act on.
+fn registration_error(error: client::Error) -> Error {
+    let reason = match error {
+        client::Error::RapiError(RAPI_INVALID_PHONE_NUMBER, _) => {
+            RegistrationError::InvalidPhoneNumber
+        }
+        client::Error::WimError(WIM_INVALID_CODE, _) => RegistrationError::InvalidCode,
+        client::Error::WimError(WIM_INVALID_CREDENTIALS, _) => RegistrationError::InvalidPassword,
+        client::Error::RapiError(RAPI_RATE_LIMITED, _)
+        | client::Error::WimError(WIM_RATE_LIMITED, _) => RegistrationError::RateLimited,
+        error => return Error::ApiError(error),
+    };
+    Error::Registration(reason)
 }
 
-pub async fn register<F, Fut>(phone_number: &str, code_validator: F) -> Result<RegistrationData>
+/// A captcha image the user has to solve.
+#[derive(Debug, Clone)]
+pub struct Captcha {
+    pub image: Vec<u8>,
+}
+
+async fn send_code_with_captcha<G, GFut>(
+    options: &ProtocolOptions,
+    phone_number: &str,
+    route: CodeRoute,
+    read_captcha: &mut G,
+) -> Result<(String, SentCode)>
 where
-    F: FnOnce() -> Fut,
-    Fut: Future<Output = Option<String>>,
+    G: FnMut(Captcha) -> GFut,
+    GFut: Future<Output = Option<String>>,
 {
+    let mut answer: Option<(String, String)> = None;
+    loop {
+        let captcha = answer
+            .as_ref()
+            .map(|(captcha_id, captcha)| (captcha_id.as_str(), captcha.as_str()));
+        match send_code(options, phone_number, route, captcha).await {
+            // A wrong answer is met with a new captcha.
+            Err(Error::ApiError(client::Error::CaptchaRequired(captcha))) => {
+                log::info!("Captcha required: {:?}", captcha);
+                let image = client::get_captcha_image(&captcha.captcha_url)
+                    .await
+                    .map_err(Error::ApiError)?;
+                let text = read_captcha(Captcha { image })
+                    .await
+                    .ok_or(Error::Registration(RegistrationError::CaptchaRequired))?;
+                answer = Some((captcha.captcha_id, text.trim().to_string()));
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn send_code(
+    options: &ProtocolOptions,
+    phone_number: &str,
+    route: CodeRoute,
+    captcha: Option<(&str, &str)>,
+) -> Result<(String, SentCode)> {
     let send_code_body = client::SendCodeBody {
         req_id: &request_id(),
         params: &client::SendCodeBodyParams {
-            phone: &phone_number,
-            language: LANGUAGE,
-            route: "sms",
+            phone: phone_number,
+            language: &options.language,
+            route: route.as_str(),
             dev_id: KEY,
             application: "icq",
+            captcha_id: captcha.map(|(captcha_id, _)| captcha_id),
+            captcha: captcha.map(|(_, captcha)| captcha),
         },
     };
     let code_response = client::send_code(&send_code_body)
         .await
-        .map_err(Error::ApiError)?;
+        .map_err(registration_error)?;
     log::info!("SendCode response: {:?}", code_response);
+    let sent_code = SentCode {
+        route,
+        code_length: Some(code_response.results.code_length)
+            .filter(|&code_length| code_length > 0)
+            .map(|code_length| code_length as usize),
+        resend_at: Instant::now() + RESEND_CODE_COOLDOWN,
+        error: None,
+    };
+    Ok((code_response.results.session_id, sent_code))
+}
 
-    let code = code_validator().await.ok_or(Error::MissingCode)?;
+pub async fn register<F, Fut, G, GFut>(
+    options: &ProtocolOptions,
+    phone_number: &str,
+    mut read_code: F,
+    mut read_captcha: G,
+) -> Result<RegistrationData>
+where
+    F: FnMut(SentCode) -> Fut,
+    Fut: Future<Output = CodeReply>,
+    G: FnMut(Captcha) -> GFut,
+    GFut: Future<Output = Option<String>>,
+{
+    let phone_number = normalize_phone_number(phone_number)
+        .ok_or(Error::Registration(RegistrationError::InvalidPhoneNumber))?;
+    let (mut session_id, mut sent_code) =
+        send_code_with_captcha(options, &phone_number, CodeRoute::Sms, &mut read_captcha).await?;
+
+    let code = loop {
+        match read_code(sent_code.clone()).await {
+            CodeReply::Code(code) => {
+                let code = code.trim().to_string();
+                if sent_code.accepts(&code) {
+                    break code;
+                }
+                let error = match sent_code.code_length {
+                    Some(code_length) => format!("The code must be {} digits long", code_length),
+                    None => "The code must be made of digits".to_string(),
+                };
+                log::warn!("{}", error);
+                sent_code.error = Some(error);
+            }
+            // Waiting out the cooldown would hold up every account, so the user is asked again.
+            CodeReply::Resend(route) => {
+                let now = Instant::now();
+                if sent_code.resend_at > now {
+                    let cooldown = (sent_code.resend_at - now).as_secs().max(1);
+                    let error = format!("A new code can be sent in {} seconds", cooldown);
+                    log::info!("{}", error);
+                    sent_code.error = Some(error);
+                    continue;
+                }
+                let (new_session_id, new_sent_code) =
+                    send_code_with_captcha(options, &phone_number, route, &mut read_captcha)
+                        .await?;
+                session_id = new_session_id;
+                sent_code = new_sent_code;
+            }
+            CodeReply::Cancel => return Err(Error::MissingCode),
+        }
+    };
 
     let login_with_phone_number_body = client::LoginWithPhoneNumberBody {
         msisdn: &phone_number,
-        trans_id: &code_response.results.session_id,
+        trans_id: &session_id,
         sms_code: &code,
-        locale: LOCALE,
+        locale: &options.locale,
         k: KEY,
         platform: "web",
         create_account: "1",
@@ -81,26 +423,62 @@ where
     };
     let login_response = client::login_with_phone_number(&login_with_phone_number_body)
         .await
-        .map_err(Error::ApiError)?;
+        .map_err(registration_error)?;
     log::info!("Login response: {:?}", login_response);
     Ok(RegistrationData {
-        session_id: code_response.results.session_id,
+        session_id,
         session_key: login_response.response.data.session_key,
         host_time: login_response.response.data.host_time,
         token: login_response.response.data.token.a,
+        device_id: String::new(),
+    })
+}
+
+/// Whether a username is a classic ICQ number rather than a phone number, which starts
+/// with `+` or `00`.
+pub fn is_uin(username: &str) -> bool {
+    (5..=10).contains(&username.len())
+        && username.chars().all(|c| c.is_ascii_digit())
+        && !username.starts_with("00")
+}
+
+/// Log in with a UIN and its password, instead of registering with a phone number.
+pub async fn client_login(uin: &str, password: &str) -> Result<RegistrationData> {
+    let client_login_body = client::ClientLoginBody {
+        s: uin,
+        pwd: password,
+        id_type: "ICQ",
+        client_name: "webicq",
+        k: KEY,
+        f: "json",
+        r: &random_id(),
+    };
+    let login_response = client::client_login(&client_login_body)
+        .await
+        .map_err(registration_error)?;
+    Ok(RegistrationData {
+        // There is no SMS transaction, and the session secret stands in for the session key.
+        session_id: String::new(),
+        session_key: login_response.response.data.session_secret,
+        host_time: login_response.response.data.host_time,
+        token: login_response.response.data.token.a,
+        device_id: String::new(),
     })
 }
 
-pub async fn start_session(registration_data: &RegistrationData) -> Result<SessionInfo> {
+pub async fn start_session(
+    registration_data: &RegistrationData,
+    options: &ProtocolOptions,
+) -> Result<SessionInfo> {
     let start_session_body = client::StartSessionBody {
         a: &registration_data.token,
         ts: timestamp(),
         k: KEY,
         view: "online",
         client_name: "webicq",
-        language: LANGUAGE,
-        device_id: &device_id(),
-        session_timeout: 2_592_000,
+        language: &options.language,
+        device_id: &registration_data.device_id,
+        session_timeout: options.session_timeout,
         assert_caps: CAPS,
         interest_caps: "",
         events: EVENTS,
@@ -111,14 +489,44 @@ pub async fn start_session(registration_data: &RegistrationData) -> Result<Sessi
         .map_err(Error::ApiError)?;
     Ok(SessionInfo {
         registration_data: registration_data.clone(),
+        options: options.clone(),
         aim_id: start_session_response.response.data.my_info.aim_id,
         aim_sid: start_session_response.response.data.aimsid,
         fetch_base_url: start_session_response.response.data.fetch_base_url,
+        started_at: timestamp(),
     })
 }
 
-pub async fn fetch_events(fetch_base_url: &str) -> Result<client::FetchEventsResponseData> {
-    let fetch_events_response = client::fetch_events(fetch_base_url)
+/// Continue a saved session, if the server still knows it.
+pub async fn resume_session(
+    registration_data: &RegistrationData,
+    options: &ProtocolOptions,
+    saved_session: SavedSession,
+) -> Result<SessionInfo> {
+    if saved_session.is_expired(options.session_timeout) {
+        return Err(Error::SessionExpired);
+    }
+    let session = SessionInfo {
+        registration_data: registration_data.clone(),
+        options: options.clone(),
+        aim_id: saved_session.aim_id,
+        aim_sid: saved_session.aim_sid,
+        fetch_base_url: saved_session.fetch_base_url,
+        // Sessions saved before the start time was kept count from now.
+        started_at: match saved_session.started_at {
+            0 => timestamp(),
+            started_at => started_at,
+        },
+    };
+    get_buddy_list(&session).await?;
+    Ok(session)
+}
+
+pub async fn fetch_events(
+    fetch_base_url: &str,
+    timeout: Duration,
+) -> Result<client::FetchEventsResponseData> {
+    let fetch_events_response = client::fetch_events(fetch_base_url, timeout)
         .await
         .map_err(Error::ApiError)?;
     Ok(fetch_events_response.response.data)
@@ -143,7 +551,7 @@ pub async fn get_chat_info_by_sn(session: &SessionInfo, sn: &str) -> Result<Chat
         aimsid: &session.aim_sid,
         req_id: &request_id(),
         params: client::GetChatInfoBodyParams {
-            member_limit: 50,
+            member_limit: session.options.member_limit,
             stamp: None,
             sn: Some(sn),
         },
@@ -154,6 +562,11 @@ pub async fn get_chat_info_by_sn(session: &SessionInfo, sn: &str) -> Result<Chat
         .map(|r| r.results)
 }
 
+// The history takes a bare language code, such as `en` for `en-US`.
+fn options_lang(options: &ProtocolOptions) -> &str {
+    options.language.split('-').next().unwrap_or_default()
+}
+
 pub async fn get_history(
     session: &SessionInfo,
     chat_sn: &str,
@@ -166,7 +579,7 @@ pub async fn get_history(
         params: client::GetHistoryBodyParams {
             count,
             from_msg_id,
-            lang: "en",
+            lang: options_lang(&session.options),
             mentions: client::GetHistoryBodyParamsMentions { resolve: false },
             patch_version: "1",
             sn: chat_sn,
@@ -183,7 +596,7 @@ pub async fn get_chat_info(session: &SessionInfo, stamp: &str) -> Result<ChatInf
         aimsid: &session.aim_sid,
         req_id: &request_id(),
         params: client::GetChatInfoBodyParams {
-            member_limit: 50,
+            member_limit: session.options.member_limit,
             stamp: Some(stamp),
             sn: None,
         },
@@ -207,6 +620,150 @@ pub async fn join_chat(session: &SessionInfo, stamp: &str) -> Result<()> {
         .map(|_| ())
 }
 
+pub async fn leave_chat(session: &SessionInfo, sn: &str) -> Result<()> {
+    let leave_chat_body = client::LeaveChatBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::SnBodyParams { sn },
+    };
+    client::leave_chat(&leave_chat_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+        .map(|_| ())
+}
+
+pub async fn modify_chat(
+    session: &SessionInfo,
+    sn: &str,
+    name: Option<&str>,
+    about: Option<&str>,
+    rules: Option<&str>,
+) -> Result<()> {
+    let mod_chat_body = client::ModChatBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::ModChatBodyParams {
+            sn,
+            name,
+            about,
+            rules,
+        },
+    };
+    client::mod_chat(&mod_chat_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+        .map(|_| ())
+}
+
+pub async fn add_chat_members(session: &SessionInfo, sn: &str, members: &[&str]) -> Result<()> {
+    let add_chat_members_body = client::AddChatMembersBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::AddChatMembersBodyParams {
+            sn,
+            members: members
+                .iter()
+                .map(|sn| client::SnBodyParams { sn })
+                .collect(),
+        },
+    };
+    client::add_chat_members(&add_chat_members_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+        .map(|_| ())
+}
+
+pub async fn search_chats(
+    session: &SessionInfo,
+    keyword: &str,
+    count: u32,
+) -> Result<Vec<client::SearchChatsResponseChat>> {
+    let search_chats_body = client::SearchChatsBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::SearchChatsBodyParams { keyword, count },
+    };
+    client::search_chats(&search_chats_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+        .map(|r| r.chats)
+}
+
+pub async fn search_messages(
+    session: &SessionInfo,
+    keyword: &str,
+    chat_sn: Option<&str>,
+    count: u32,
+) -> Result<client::SearchMessagesResponseData> {
+    let search_messages_body = client::SearchMessagesBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::SearchMessagesBodyParams {
+            keyword,
+            sn: chat_sn,
+            count,
+        },
+    };
+    client::search_messages(&search_messages_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+pub async fn resolve_pending(
+    session: &SessionInfo,
+    sn: &str,
+    members: &[&str],
+    approve: bool,
+) -> Result<()> {
+    let resolve_pending_body = client::ResolvePendingBody {
+        aimsid: &session.aim_sid,
+        req_id: &request_id(),
+        params: client::ResolvePendingBodyParams {
+            sn,
+            approve,
+            members: members
+                .iter()
+                .map(|sn| client::SnBodyParams { sn })
+                .collect(),
+        },
+    };
+    client::resolve_pending(&resolve_pending_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+        .map(|_| ())
+}
+
+pub async fn get_buddy_list(session: &SessionInfo) -> Result<client::events::BuddyListData> {
+    let get_presence_body = client::GetPresenceBody {
+        bl: "1",
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::get_presence(&get_presence_body)
+        .await
+        .map_err(Error::ApiError)
+        .map(|r| r.response.data)
+}
+
+pub async fn end_session(session: &SessionInfo) -> Result<()> {
+    let end_session_body = client::EndSessionBody {
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::end_session(&end_session_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
 pub async fn send_im(session: &SessionInfo, to_sn: &str, message: &str) -> Result<MsgInfo> {
     let send_im_body = client::SendIMBody {
         t: to_sn,
@@ -222,6 +779,102 @@ pub async fn send_im(session: &SessionInfo, to_sn: &str, message: &str) -> Resul
         .map(|r| r.response.data)
 }
 
+pub async fn add_buddy(session: &SessionInfo, sn: &str, group: &str) -> Result<()> {
+    let add_buddy_body = client::AddBuddyBody {
+        buddy: sn,
+        group,
+        pre_authorized: "1",
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::add_buddy(&add_buddy_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+pub async fn remove_buddy(session: &SessionInfo, sn: &str) -> Result<()> {
+    let remove_buddy_body = client::RemoveBuddyBody {
+        buddy: sn,
+        all_groups: "1",
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::remove_buddy(&remove_buddy_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+pub async fn set_buddy_friendly(session: &SessionInfo, sn: &str, friendly: &str) -> Result<()> {
+    let set_buddy_attribute_body = client::SetBuddyAttributeBody {
+        buddy: sn,
+        friendly: Some(friendly),
+        mute: None,
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::set_buddy_attribute(&set_buddy_attribute_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+pub async fn set_buddy_muted(session: &SessionInfo, sn: &str, muted: bool) -> Result<()> {
+    let set_buddy_attribute_body = client::SetBuddyAttributeBody {
+        buddy: sn,
+        friendly: None,
+        mute: Some(if muted { MUTE_FOREVER } else { 0 }),
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::set_buddy_attribute(&set_buddy_attribute_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+pub async fn move_buddy(
+    session: &SessionInfo,
+    sn: &str,
+    group: &str,
+    new_group: &str,
+) -> Result<()> {
+    let move_buddy_body = client::MoveBuddyBody {
+        buddy: sn,
+        group,
+        new_group,
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::move_buddy(&move_buddy_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
+// Users blocked from the ICQ clients may be on either the block or the ignore list,
+// so unblocking takes them off both.
+pub async fn set_ignored(session: &SessionInfo, sn: &str, ignored: bool) -> Result<()> {
+    let set_permit_deny_body = client::SetPermitDenyBody {
+        pd_ignore: if ignored { Some(sn) } else { None },
+        pd_ignore_remove: if ignored { None } else { Some(sn) },
+        pd_block_remove: if ignored { None } else { Some(sn) },
+        r: &request_id(),
+        f: "json",
+        aimsid: &session.aim_sid,
+    };
+    client::set_permit_deny(&set_permit_deny_body)
+        .await
+        .and_then(|r| r.into_result())
+        .map_err(Error::ApiError)
+}
+
 fn request_id() -> String {
     format!("{}-{}", random_id(), timestamp())
 }
@@ -231,15 +884,85 @@ fn random_id() -> String {
     random_id.to_string()
 }
 
-fn timestamp() -> u32 {
+pub fn timestamp() -> u32 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_secs() as u32
 }
 
-fn device_id() -> String {
-    Uuid::new_v5(&Uuid::NAMESPACE_DNS, random_id().as_bytes())
+// A random (version 4) UUID, the device id must not collide with other installs.
+pub fn new_device_id() -> String {
+    uuid::Builder::from_bytes(rand::random())
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Random)
+        .build()
         .to_hyphenated()
         .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_phone_number_valid() {
+        assert_eq!(
+            normalize_phone_number("+33 6 12 34 56 78").as_deref(),
+            Some("+33612345678")
+        );
+        assert_eq!(
+            normalize_phone_number("0033612345678").as_deref(),
+            Some("+33612345678")
+        );
+        assert_eq!(
+            normalize_phone_number("+1 (555) 123-4567").as_deref(),
+            Some("+15551234567")
+        );
+    }
+
+    #[test]
+    fn normalize_phone_number_invalid() {
+        assert_eq!(normalize_phone_number(""), None);
+        assert_eq!(normalize_phone_number("33612345678"), None);
+        assert_eq!(normalize_phone_number("+331234"), None);
+        assert_eq!(normalize_phone_number("+0612345678"), None);
+        assert_eq!(normalize_phone_number("+33 6 12 34 56 7a"), None);
+        assert_eq!(normalize_phone_number("+1234567890123456"), None);
+    }
+
+    #[test]
+    fn is_uin_bare_digits() {
+        assert!(is_uin("12345"));
+        assert!(is_uin("612345678"));
+        assert!(!is_uin("1234"));
+        assert!(!is_uin("+33612345678"));
+        assert!(!is_uin("0033612345"));
+        assert!(!is_uin("12345a"));
+    }
+
+    fn sent_code(code_length: Option<usize>) -> SentCode {
+        SentCode {
+            route: CodeRoute::Sms,
+            code_length,
+            resend_at: Instant::now(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn sent_code_accepts_expected_length() {
+        let sent_code = sent_code(Some(6));
+        assert!(sent_code.accepts("123456"));
+        assert!(!sent_code.accepts("12345"));
+        assert!(!sent_code.accepts("12345a"));
+    }
+
+    #[test]
+    fn sent_code_accepts_any_length() {
+        let sent_code = sent_code(None);
+        assert!(sent_code.accepts("1234"));
+        assert!(!sent_code.accepts(""));
+        assert!(!sent_code.accepts("12 34"));
+    }
+}
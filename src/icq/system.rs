@@ -1,14 +1,25 @@
+use super::export;
+use super::history;
 use super::poller;
 use super::protocol;
+use crate::date;
 use crate::logging;
 use crate::messages::{
-    AccountInfo, FdSender, GetChatInfoMessage, GetHistoryMessage, ICQSystemHandle, JoinChatMessage,
-    PurpleMessage, SendMsgMessage, SystemMessage,
+    AccountAction, AccountActionMessage, AccountInfo, AddBuddyMessage, AliasBuddyMessage,
+    ExportHistoryMessage, ExportHistoryMessageData, FdSender, FetchContextMessage,
+    GetChatInfoMessage, GetChatInfoMessageData, GetHistoryMessage, ICQSystemHandle,
+    InviteToChatMessage, JoinChatMessage, LeaveChatMessage, LogoutMessage, LogoutMessageData,
+    ModifyChatMessage, MoveBuddyMessage, PurpleMessage, RemoveBuddyMessage, SearchChatsMessage,
+    SearchMessagesMessage, SendMsgMessage, SetBlockedMessage, SetMutedMessage, SystemMessage,
 };
-use crate::{ChatInfo, Handle};
+use crate::{AccountDataBox, ChatInfo, Handle, MsgSource, PublicChatInfo};
 use async_std::channel::{self, Receiver};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 
 const CHANNEL_CAPACITY: usize = 1024;
+const SEARCH_RESULTS_COUNT: u32 = 20;
+const CONTEXT_SIZE: i32 = 10;
 
 pub fn spawn() -> ICQSystemHandle {
     let (input_rx, input_tx) = os_pipe::pipe().unwrap();
@@ -27,6 +38,31 @@ pub fn spawn() -> ICQSystemHandle {
     }
 }
 
+// The device id of the account, made up and saved the first time it is needed.
+fn account_device_id(account: &mut purple::Account) -> String {
+    let device_id = account.get_string(protocol::RegistrationData::DEVICE_ID_SETTING_KEY, "");
+    if !device_id.is_empty() {
+        return device_id;
+    }
+    let device_id = protocol::new_device_id();
+    account.set_string(
+        protocol::RegistrationData::DEVICE_ID_SETTING_KEY,
+        &device_id,
+    );
+    device_id
+}
+
+// The session is gone once the account is logged out, while messages sent before may still
+// be waiting.
+async fn current_session(protocol_data: &AccountDataBox) -> Result<protocol::SessionInfo, String> {
+    protocol_data
+        .session
+        .read()
+        .await
+        .clone()
+        .ok_or_else(|| "The account has no session".to_string())
+}
+
 pub fn run(tx: FdSender<SystemMessage>, rx: Receiver<PurpleMessage>) {
     logging::set_thread_logger(logging::RemoteLogger(tx.clone()));
     log::info!("Starting ICQ");
@@ -56,10 +92,25 @@ impl ICQSystem {
             log::info!("Message: {:?}", purple_message);
             let result = match purple_message {
                 PurpleMessage::Login(account_info) => self.login(account_info).await,
+                PurpleMessage::Logout(m) => self.logout(m).await,
                 PurpleMessage::JoinChat(m) => self.join_chat(m).await,
+                PurpleMessage::LeaveChat(m) => self.leave_chat(m).await,
+                PurpleMessage::ModifyChat(m) => self.modify_chat(m).await,
+                PurpleMessage::InviteToChat(m) => self.invite_to_chat(m).await,
+                PurpleMessage::SearchChats(m) => self.search_chats(m).await,
+                PurpleMessage::AddBuddy(m) => self.add_buddy(m).await,
+                PurpleMessage::RemoveBuddy(m) => self.remove_buddy(m).await,
+                PurpleMessage::AliasBuddy(m) => self.alias_buddy(m).await,
+                PurpleMessage::MoveBuddy(m) => self.move_buddy(m).await,
+                PurpleMessage::SetBlocked(m) => self.set_blocked(m).await,
+                PurpleMessage::SetMuted(m) => self.set_muted(m).await,
                 PurpleMessage::SendMsg(m) => self.send_msg(m).await,
                 PurpleMessage::GetChatInfo(m) => self.get_chat_info(m).await,
                 PurpleMessage::GetHistory(m) => self.get_history(m).await,
+                PurpleMessage::FetchContext(m) => self.fetch_context(m).await,
+                PurpleMessage::SearchMessages(m) => self.search_messages(m).await,
+                PurpleMessage::ExportHistory(m) => self.export_history(m).await,
+                PurpleMessage::AccountAction(m) => self.account_action(m).await,
             };
             if let Err(error) = result {
                 log::error!("Error handling message: {}", error);
@@ -70,7 +121,6 @@ impl ICQSystem {
 
     async fn login(&mut self, account_info: AccountInfo) -> std::result::Result<(), String> {
         log::debug!("login");
-        let phone_number = { account_info.protocol_data.phone_number.clone() };
         let handle = &account_info.handle;
         let mut registered_account_info = {
             self.tx
@@ -92,6 +142,7 @@ impl ICQSystem {
                             host_time: account
                                 .get_int(protocol::RegistrationData::HOST_TIME_SETTING_KEY, 0)
                                 as u32,
+                            device_id: account_device_id(account),
                         })
                     }
                 })
@@ -99,20 +150,7 @@ impl ICQSystem {
                 .ok_or_else(|| "Failed to read settings".to_string())?
         };
         if registered_account_info.is_none() {
-            let info = protocol::register(&phone_number, || {
-                log::debug!("read_code");
-                self.read_code(&account_info.handle)
-            })
-            .await
-            .map_err(|e| format!("Failed to register account: {:?}", e))?;
-
-            self.tx
-                .account_proxy(&handle)
-                .set_settings(info.clone())
-                .await
-                .map_err(|e| format!("Failed to write settings: {:?}", e))?;
-
-            registered_account_info = Some(info);
+            registered_account_info = Some(self.register(&account_info).await?);
         }
 
         log::debug!("Registered account info: {:?}", registered_account_info);
@@ -133,26 +171,115 @@ impl ICQSystem {
                 .set_state(purple::PurpleConnectionState::PURPLE_CONNECTING)
                 .await;
 
-            let session_info = protocol::start_session(&registered_account_info).await;
+            let saved_session = self
+                .tx
+                .account_proxy(&handle)
+                .exec(|account| {
+                    let aim_sid =
+                        account.get_string(protocol::SavedSession::AIM_SID_SETTING_KEY, "");
+                    if aim_sid.is_empty() {
+                        None
+                    } else {
+                        Some(protocol::SavedSession {
+                            aim_id: account
+                                .get_string(protocol::SavedSession::AIM_ID_SETTING_KEY, ""),
+                            aim_sid,
+                            fetch_base_url: account
+                                .get_string(protocol::SavedSession::FETCH_BASE_URL_SETTING_KEY, ""),
+                            ts: account.get_string(protocol::SavedSession::TS_SETTING_KEY, ""),
+                            started_at: account
+                                .get_int(protocol::SavedSession::STARTED_AT_SETTING_KEY, 0)
+                                as u32,
+                        })
+                    }
+                })
+                .await
+                .flatten();
+
+            // A resumed session continues the event stream, so nothing needs catching up.
+            let session_info = match saved_session {
+                Some(saved_session) => {
+                    let ts = saved_session.ts.clone();
+                    match protocol::resume_session(
+                        &registered_account_info,
+                        &account_info.protocol_data.options,
+                        saved_session,
+                    )
+                    .await
+                    {
+                        Ok(session) => {
+                            log::info!("Resumed session");
+                            account_info
+                                .protocol_data
+                                .needs_catch_up
+                                .store(false, Ordering::Relaxed);
+                            Ok((session, ts))
+                        }
+                        Err(error) => {
+                            log::info!("Failed to resume session: {:?}", error);
+                            protocol::start_session(
+                                &registered_account_info,
+                                &account_info.protocol_data.options,
+                            )
+                            .await
+                            .map(|session| (session, protocol::timestamp().to_string()))
+                        }
+                    }
+                }
+                None => protocol::start_session(
+                    &registered_account_info,
+                    &account_info.protocol_data.options,
+                )
+                .await
+                .map(|session| (session, protocol::timestamp().to_string())),
+            };
+
+            // A revoked token can't be used again, so the account is registered anew.
+            let session_info = match session_info {
+                Err(error) if error.is_token_rejected() => {
+                    log::warn!("Auth token rejected: {:?}", error);
+                    self.forget_registration(&account_info).await;
+                    let registered_account_info = self.register(&account_info).await?;
+                    protocol::start_session(
+                        &registered_account_info,
+                        &account_info.protocol_data.options,
+                    )
+                    .await
+                    .map(|session| (session, protocol::timestamp().to_string()))
+                }
+                session_info => session_info,
+            };
             log::debug!("Session info: {:?}", session_info);
             match session_info {
-                Ok(session) => {
+                Ok((session, ts)) => {
                     self.tx
                         .connection_proxy(&handle)
                         .set_state(purple::PurpleConnectionState::PURPLE_CONNECTED)
                         .await;
+                    if let Err(error) = self
+                        .tx
+                        .account_proxy(&handle)
+                        .set_settings(protocol::SavedSession::new(&session, ts))
+                        .await
+                    {
+                        log::error!("Failed to save session: {:?}", error);
+                    }
                     (*account_info.protocol_data.session.write().await) = Some(session);
-                    async_std::task::spawn_local(poller::fetch_events_loop(
-                        self.tx.clone(),
-                        account_info.clone(),
-                    ));
+                    let poller = poller::PollerHandle::spawn(self.tx.clone(), account_info.clone());
+                    (*account_info.protocol_data.poller.write().await) = Some(poller);
                 }
                 Err(error) => {
                     let error_message = format!("Failed to start session: {:?}", error);
+                    // Only a rejected token is worth asking for new credentials.
+                    let reason = if error.is_token_rejected() {
+                        purple::PurpleConnectionError::PURPLE_CONNECTION_ERROR_AUTHENTICATION_FAILED
+                    } else {
+                        purple::PurpleConnectionError::PURPLE_CONNECTION_ERROR_NETWORK_ERROR
+                    };
                     self.tx
                         .connection_proxy(&handle)
-                        .error_reason(purple::PurpleConnectionError::PURPLE_CONNECTION_ERROR_AUTHENTICATION_FAILED,
-                                      error_message.clone()).await;
+                        .error_reason(reason, error_message.clone())
+                        .await;
                     return Err(error_message);
                 }
             }
@@ -160,14 +287,222 @@ impl ICQSystem {
         Ok(())
     }
 
-    async fn read_code(&mut self, handle: &Handle) -> Option<String> {
-        let code = self
+    // Register with a phone number, or log in with a UIN and password, and save the result.
+    async fn register(
+        &mut self,
+        account_info: &AccountInfo,
+    ) -> std::result::Result<protocol::RegistrationData, String> {
+        let username = &account_info.protocol_data.username;
+        let info = match &account_info.protocol_data.password {
+            Some(password) if protocol::is_uin(username) => {
+                protocol::client_login(username, password).await
+            }
+            _ => {
+                let tx = self.tx.clone();
+                protocol::register(
+                    &account_info.protocol_data.options,
+                    username,
+                    |sent_code| {
+                        log::debug!("read_code");
+                        Self::read_code(tx.clone(), account_info.handle.clone(), sent_code)
+                    },
+                    |captcha| Self::read_captcha(tx.clone(), account_info.handle.clone(), captcha),
+                )
+                .await
+            }
+        };
+        let mut info = match info {
+            Ok(info) => info,
+            Err(error) => {
+                log::error!("Failed to register account: {:?}", error);
+                let error_message = match error {
+                    protocol::Error::Registration(reason) => reason.to_string(),
+                    error => format!("Failed to register account: {:?}", error),
+                };
+                self.tx
+                    .connection_proxy(&account_info.handle)
+                    .error_reason(
+                        purple::PurpleConnectionError::PURPLE_CONNECTION_ERROR_AUTHENTICATION_FAILED,
+                        error_message.clone(),
+                    )
+                    .await;
+                return Err(error_message);
+            }
+        };
+        info.device_id = self
+            .tx
+            .account_proxy(&account_info.handle)
+            .exec(account_device_id)
+            .await
+            .unwrap_or_else(protocol::new_device_id);
+
+        self.tx
+            .account_proxy(&account_info.handle)
+            .set_settings(info.clone())
+            .await
+            .map_err(|e| format!("Failed to write settings: {:?}", e))?;
+
+        Ok(info)
+    }
+
+    async fn forget_registration(&mut self, account_info: &AccountInfo) {
+        let handle = &account_info.handle;
+        self.clear_saved_login(handle).await;
+
+        let username = &account_info.protocol_data.username;
+        let secondary =
+            if account_info.protocol_data.password.is_some() && protocol::is_uin(username) {
+                "Logging in again with your password."
+            } else {
+                "A new code will be sent to your phone by SMS."
+            };
+        self.tx
+            .connection_proxy(handle)
+            .notify_message(
+                purple::PurpleNotifyMsgType::PURPLE_NOTIFY_MSG_INFO,
+                "Login expired".into(),
+                format!("The saved login of {} is no longer valid", username),
+                Some(secondary.into()),
+            )
+            .await;
+    }
+
+    // Clear the registration and the session saved in the account settings.
+    async fn clear_saved_login(&mut self, handle: &Handle) {
+        if let Err(error) = self
             .tx
+            .account_proxy(handle)
+            .set_settings(protocol::RegistrationData::default())
+            .await
+        {
+            log::error!("Failed to clear registration: {:?}", error);
+        }
+        if let Err(error) = self
+            .tx
+            .account_proxy(handle)
+            .set_settings(protocol::SavedSession::default())
+            .await
+        {
+            log::error!("Failed to clear the saved session: {:?}", error);
+        }
+    }
+
+    async fn logout(&mut self, message: LogoutMessage) -> std::result::Result<(), String> {
+        log::debug!("logout");
+        let protocol_data = &message.protocol_data;
+
+        // The poller may be in the middle of a long poll, which is dropped with it.
+        let poller = protocol_data.poller.write().await.take();
+        if let Some(poller) = poller {
+            poller.stop().await;
+        }
+
+        let session = protocol_data.session.write().await.take();
+        match session {
+            Some(session) if message.message_data.end_session => protocol::end_session(&session)
+                .await
+                .map_err(|e| format!("Failed to end session: {:?}", e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn account_action(&mut self, message: AccountActionMessage) -> Result<(), String> {
+        let account_info = AccountInfo {
+            handle: message.handle,
+            protocol_data: message.protocol_data,
+        };
+        match message.message_data.action {
+            AccountAction::Resync => self.resync(account_info).await,
+            AccountAction::ShowAccountInfo => self.show_account_info(account_info).await,
+            AccountAction::ForgetLogin => self.forget_login(account_info).await,
+        }
+    }
+
+    async fn resync(&mut self, account_info: AccountInfo) -> Result<(), String> {
+        current_session(&account_info.protocol_data).await?;
+        poller::resync(self.tx.clone(), &account_info).await;
+        Ok(())
+    }
+
+    async fn show_account_info(&mut self, account_info: AccountInfo) -> Result<(), String> {
+        let session = current_session(&account_info.protocol_data).await?;
+        let username = &account_info.protocol_data.username;
+        let username_label = if protocol::is_uin(username) {
+            "ICQ number"
+        } else {
+            "Phone number"
+        };
+        let session_age = protocol::timestamp().saturating_sub(session.started_at);
+        let info = format!(
+            "ICQ ID: {}\n{}: {}\nDevice ID: {}\nSession started: {} UTC ({} ago)",
+            session.aim_id,
+            username_label,
+            username,
+            session.registration_data.device_id,
+            date::format_time(session.started_at.into()),
+            date::format_duration(session_age),
+        );
+        self.tx
+            .connection_proxy(&account_info.handle)
+            .notify_message(
+                purple::PurpleNotifyMsgType::PURPLE_NOTIFY_MSG_INFO,
+                "Account info".into(),
+                format!("Logged in as {}", session.aim_id),
+                Some(info),
+            )
+            .await;
+        Ok(())
+    }
+
+    async fn forget_login(&mut self, account_info: AccountInfo) -> Result<(), String> {
+        let handle = &account_info.handle;
+
+        // Stopped before the settings are cleared, so that the poller doesn't save the session
+        // again. The session belongs to the forgotten login, so it is ended too.
+        let result = self
+            .logout(LogoutMessage {
+                handle: handle.clone(),
+                protocol_data: account_info.protocol_data.clone(),
+                message_data: LogoutMessageData { end_session: true },
+            })
+            .await;
+        self.clear_saved_login(handle).await;
+        self.tx
+            .account_proxy(handle)
+            .exec_no_return(|account| account.disconnect())
+            .await;
+        result
+    }
+
+    async fn read_code(
+        mut tx: FdSender<SystemMessage>,
+        handle: Handle,
+        sent_code: protocol::SentCode,
+    ) -> protocol::CodeReply {
+        let (title, secondary) = match sent_code.route {
+            protocol::CodeRoute::Sms => (
+                "SMS Code",
+                "You will be sent an SMS message containing your auth code.",
+            ),
+            protocol::CodeRoute::Voice => (
+                "Voice Call Code",
+                "You will receive a call reading out your auth code.",
+            ),
+        };
+        let primary = match sent_code.code_length {
+            Some(code_length) => format!("Enter the {} digit code", code_length),
+            None => "Enter the code".to_string(),
+        };
+        let secondary = match &sent_code.error {
+            Some(error) => format!("{}\n{}", error, secondary),
+            None => secondary.to_string(),
+        };
+        let code = tx
             .account_proxy(&handle)
             .request_input(
-                Some("SMS Code".into()),
-                Some("Enter SMS code".into()),
-                Some("You will be sent an SMS message containing your auth code.".into()),
+                Some(title.into()),
+                Some(primary),
+                Some(secondary),
                 None,
                 false,
                 false,
@@ -178,12 +513,59 @@ impl ICQSystem {
             )
             .await;
         log::info!("Code: {:?}", code);
-        code
+        if let Some(code) = code {
+            return protocol::CodeReply::Code(code);
+        }
+
+        let cooldown = sent_code
+            .resend_at
+            .saturating_duration_since(Instant::now())
+            .as_secs();
+        let secondary = if cooldown > 0 {
+            format!("A new code can be sent in {} seconds.", cooldown)
+        } else {
+            "A new code can be sent once a minute.".to_string()
+        };
+        let action = tx
+            .account_proxy(&handle)
+            .request_action(
+                Some("Didn't get the code?".into()),
+                Some("Send a new code".into()),
+                Some(secondary),
+                0,
+                None,
+                vec!["Send a new SMS".into(), "Call me".into(), "Cancel".into()],
+            )
+            .await;
+        match action {
+            Some(0) => protocol::CodeReply::Resend(protocol::CodeRoute::Sms),
+            Some(1) => protocol::CodeReply::Resend(protocol::CodeRoute::Voice),
+            _ => protocol::CodeReply::Cancel,
+        }
+    }
+
+    async fn read_captcha(
+        mut tx: FdSender<SystemMessage>,
+        handle: Handle,
+        captcha: protocol::Captcha,
+    ) -> Option<String> {
+        let text = tx
+            .account_proxy(&handle)
+            .request_captcha(
+                "Captcha".into(),
+                "Enter the text in the image to get a code".into(),
+                captcha.image,
+                "Send".into(),
+                "Cancel".into(),
+            )
+            .await;
+        log::info!("Captcha: {:?}", text);
+        text
     }
 
     async fn get_chat_info(&mut self, message: GetChatInfoMessage) -> Result<(), String> {
         log::info!("Get chat info sn: {}", message.message_data.sn);
-        let session = { message.protocol_data.session.read().await.clone().unwrap() };
+        let session = current_session(&message.protocol_data).await?;
         let chat_info_response = protocol::get_chat_info_by_sn(&session, &message.message_data.sn)
             .await
             .map_err(|e| format!("Failed to get chat info: {:?}", e))?;
@@ -202,7 +584,7 @@ impl ICQSystem {
 
     async fn join_chat(&mut self, message: JoinChatMessage) -> Result<(), String> {
         log::info!("Joining stamp: {}", message.message_data.stamp);
-        let session = { message.protocol_data.session.read().await.clone().unwrap() };
+        let session = current_session(&message.protocol_data).await?;
         let stamp = message.message_data.stamp;
         // Handle shareable URLs: https://icq.im/XXXXXXXXXXXXXX
         let stamp = if stamp.contains("icq.im/") {
@@ -218,6 +600,23 @@ impl ICQSystem {
             .await
             .map_err(|e| format!("Failed to get chat info: {:?}", e))?;
 
+        if chat_info_response.you_pending {
+            log::info!("Join pending approval: {}", chat_info_response.sn);
+            self.tx
+                .connection_proxy(&message.handle)
+                .notify_message(
+                    purple::PurpleNotifyMsgType::PURPLE_NOTIFY_MSG_INFO,
+                    "Join request sent".into(),
+                    format!("Joining {} is pending approval", chat_info_response.name),
+                    Some(
+                        "An admin of this chat must approve your request before you can join it."
+                            .into(),
+                    ),
+                )
+                .await;
+            return Ok(());
+        }
+
         self.tx
             .handle_proxy(&message.handle)
             .exec_no_return(move |plugin, protocol_data| {
@@ -233,21 +632,129 @@ impl ICQSystem {
         Ok(())
     }
 
-    async fn get_history(&mut self, get_history_message: GetHistoryMessage) -> Result<(), String> {
-        let session = {
-            get_history_message
-                .protocol_data
-                .session
-                .read()
-                .await
-                .clone()
-                .unwrap()
+    async fn leave_chat(&mut self, message: LeaveChatMessage) -> Result<(), String> {
+        log::info!("Leaving chat: {}", message.message_data.sn);
+        let session = current_session(&message.protocol_data).await?;
+        let sn = message.message_data.sn;
+
+        protocol::leave_chat(&session, &sn)
+            .await
+            .map_err(|e| format!("Failed to leave chat: {:?}", e))?;
+
+        self.tx
+            .handle_proxy(&message.handle)
+            .exec_no_return(move |plugin, protocol_data| {
+                let connection = &mut protocol_data.connection;
+                plugin.chat_left(connection, &sn);
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn modify_chat(&mut self, message: ModifyChatMessage) -> Result<(), String> {
+        log::info!("Modifying chat: {:?}", message.message_data);
+        let session = current_session(&message.protocol_data).await?;
+        let sn = message.message_data.sn.clone();
+
+        // The server knows who the admins are, the member list may be cut off.
+        let data = &message.message_data;
+        if let Err(error) = protocol::modify_chat(
+            &session,
+            &sn,
+            data.name.as_deref(),
+            data.about.as_deref(),
+            data.rules.as_deref(),
+        )
+        .await
+        {
+            let error_message = if error.is_permission_denied() {
+                "Only chat admins can change the chat title, topic or rules."
+            } else {
+                "Failed to change the chat."
+            };
+            let error_sn = sn.clone();
+            self.tx
+                .handle_proxy(&message.handle)
+                .exec_no_return(move |plugin, protocol_data| {
+                    let connection = &mut protocol_data.connection;
+                    plugin.write_chat_system_message(connection, &error_sn, error_message);
+                })
+                .await;
+            return Err(format!("Failed to modify chat: {:?}", error));
+        }
+
+        self.get_chat_info(GetChatInfoMessage {
+            handle: message.handle,
+            protocol_data: message.protocol_data,
+            message_data: GetChatInfoMessageData { sn },
+        })
+        .await
+    }
+
+    async fn invite_to_chat(&mut self, message: InviteToChatMessage) -> Result<(), String> {
+        log::info!("Inviting to chat: {:?}", message.message_data);
+        let session = current_session(&message.protocol_data).await?;
+        let sn = message.message_data.sn.clone();
+        let who = message.message_data.who.clone();
+
+        if let Err(error) = protocol::add_chat_members(&session, &sn, &[&who]).await {
+            let error_sn = sn.clone();
+            self.tx
+                .handle_proxy(&message.handle)
+                .exec_no_return(move |plugin, protocol_data| {
+                    let connection = &mut protocol_data.connection;
+                    plugin.write_chat_system_message(
+                        connection,
+                        &error_sn,
+                        &format!("Failed to invite {} to the chat.", who),
+                    );
+                })
+                .await;
+            return Err(format!("Failed to invite to chat: {:?}", error));
+        }
+
+        self.get_chat_info(GetChatInfoMessage {
+            handle: message.handle,
+            protocol_data: message.protocol_data,
+            message_data: GetChatInfoMessageData { sn },
+        })
+        .await
+    }
+
+    async fn search_chats(&mut self, message: SearchChatsMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let chats = protocol::search_chats(&session, &data.keyword, data.count).await;
+
+        // Always complete the room list, even on errors, so it doesn't stay in progress.
+        let (rooms, result) = match chats {
+            Ok(chats) => (
+                chats.into_iter().map(PublicChatInfo::from).collect(),
+                Ok(()),
+            ),
+            Err(error) => (
+                Vec::new(),
+                Err(format!("Failed to search chats: {:?}", error)),
+            ),
         };
+        self.tx
+            .handle_proxy(&message.handle)
+            .exec_no_return(move |plugin, protocol_data| {
+                plugin.roomlist_loaded(&protocol_data.data.username, &rooms);
+            })
+            .await;
+
+        result
+    }
+
+    async fn get_history(&mut self, get_history_message: GetHistoryMessage) -> Result<(), String> {
+        let session = current_session(&get_history_message.protocol_data).await?;
         let sn = &get_history_message.message_data.sn;
-        let from_msg_id = &get_history_message.message_data.from_msg_id;
-        let count = get_history_message.message_data.count;
+        let from_msg_id = get_history_message.message_data.from_msg_id.as_deref();
+        let limit = &get_history_message.message_data.limit;
 
-        let history = protocol::get_history(&session, sn, from_msg_id, count)
+        let history = history::fetch_backwards(&session, sn, from_msg_id, limit)
             .await
             .map_err(|e| format!("Failed to get history: {:?}", e))?;
 
@@ -255,24 +762,233 @@ impl ICQSystem {
             self.tx.clone(),
             session,
             get_history_message.handle,
+            &get_history_message.protocol_data.tasks,
+            sn,
+            &history.persons,
+            None,
+            &history.messages,
+            MsgSource::History,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn fetch_context(&mut self, message: FetchContextMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let sn = &message.message_data.sn;
+
+        let history =
+            history::fetch_around(&session, sn, &message.message_data.msg_id, CONTEXT_SIZE)
+                .await
+                .map_err(|e| format!("Failed to get context: {:?}", e))?;
+
+        super::poller::process_hist_dlg_state_messages(
+            self.tx.clone(),
+            session,
+            message.handle,
+            &message.protocol_data.tasks,
             sn,
             &history.persons,
             None,
             &history.messages,
+            MsgSource::History,
         )
         .await;
 
         Ok(())
     }
 
+    async fn search_messages(&mut self, message: SearchMessagesMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = message.message_data;
+        let chat_sn = if data.all_chats {
+            None
+        } else {
+            Some(data.sn.as_str())
+        };
+
+        let found =
+            protocol::search_messages(&session, &data.keyword, chat_sn, SEARCH_RESULTS_COUNT)
+                .await
+                .map_err(|e| format!("Failed to search messages: {:?}", e))?;
+
+        let mut notice = if found.results.is_empty() {
+            format!(
+                "No messages found for \"{}\"",
+                htmlescape::encode_minimal(&data.keyword)
+            )
+        } else {
+            format!(
+                "Messages found for \"{}\", use /context &lt;msg_id&gt; in their chat to see more:",
+                htmlescape::encode_minimal(&data.keyword)
+            )
+        };
+        for result in &found.results {
+            let author_sn = match &result.message.chat {
+                Some(chat) => &chat.sender,
+                None => &result.sn,
+            };
+            let author = poller::find_author_friendly(author_sn, &found.persons);
+            let chat = if data.all_chats {
+                format!(
+                    " in {}",
+                    poller::find_author_friendly(&result.sn, &found.persons)
+                )
+            } else {
+                String::new()
+            };
+            notice.push_str(&format!(
+                "<br>[{}] {}{}: {} ({})",
+                date::format_time(result.message.time),
+                htmlescape::encode_minimal(author),
+                htmlescape::encode_minimal(&chat),
+                htmlescape::encode_minimal(result.message.text.as_deref().unwrap_or_default()),
+                result.message.msg_id,
+            ));
+        }
+
+        let sn = data.sn;
+        self.tx
+            .handle_proxy(&message.handle)
+            .exec_no_return(move |plugin, protocol_data| {
+                let connection = &mut protocol_data.connection;
+                plugin.write_chat_system_message(connection, &sn, &notice);
+            })
+            .await;
+
+        Ok(())
+    }
+
+    async fn export_history(&mut self, message: ExportHistoryMessage) -> Result<(), String> {
+        log::info!("Exporting history: {:?}", message.message_data);
+        let session = current_session(&message.protocol_data).await?;
+
+        // A long history takes a while, so it doesn't hold up the other messages.
+        message.protocol_data.tasks.spawn(export_history(
+            self.tx.clone(),
+            session,
+            message.handle,
+            message.message_data,
+        ));
+        Ok(())
+    }
+
+    async fn add_buddy(&mut self, message: AddBuddyMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result = protocol::add_buddy(&session, &data.sn, &data.group).await;
+        self.buddy_list_edited(&message.handle, "add", &data.sn, result)
+            .await
+    }
+
+    async fn remove_buddy(&mut self, message: RemoveBuddyMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result = protocol::remove_buddy(&session, &data.sn).await;
+        self.buddy_list_edited(&message.handle, "remove", &data.sn, result)
+            .await
+    }
+
+    async fn alias_buddy(&mut self, message: AliasBuddyMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result = protocol::set_buddy_friendly(&session, &data.sn, &data.alias).await;
+        self.buddy_list_edited(&message.handle, "rename", &data.sn, result)
+            .await
+    }
+
+    async fn move_buddy(&mut self, message: MoveBuddyMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result =
+            protocol::move_buddy(&session, &data.sn, &data.old_group, &data.new_group).await;
+        self.buddy_list_edited(&message.handle, "move", &data.sn, result)
+            .await
+    }
+
+    async fn set_blocked(&mut self, message: SetBlockedMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result = protocol::set_ignored(&session, &data.sn, data.blocked).await;
+        let action = if data.blocked { "block" } else { "unblock" };
+        self.buddy_list_edited(&message.handle, action, &data.sn, result)
+            .await
+    }
+
+    async fn set_muted(&mut self, message: SetMutedMessage) -> Result<(), String> {
+        let session = current_session(&message.protocol_data).await?;
+        let data = &message.message_data;
+        let result = protocol::set_buddy_muted(&session, &data.sn, data.muted).await;
+        let action = if data.muted { "mute" } else { "unmute" };
+        self.buddy_list_edited(&message.handle, action, &data.sn, result)
+            .await
+    }
+
+    // The server sends an updated buddy list or privacy lists after every change, which replace
+    // the local ones. A failed change is only reported, and undone by the next update.
+    async fn buddy_list_edited(
+        &mut self,
+        handle: &Handle,
+        action: &str,
+        sn: &str,
+        result: protocol::Result<()>,
+    ) -> Result<(), String> {
+        if let Err(error) = result {
+            self.tx
+                .connection_proxy(handle)
+                .notify_message(
+                    purple::PurpleNotifyMsgType::PURPLE_NOTIFY_MSG_ERROR,
+                    "Buddy list error".into(),
+                    format!("Failed to {} {}", action, sn),
+                    Some(format!("{:?}", error)),
+                )
+                .await;
+            return Err(format!("Failed to {} buddy {}: {:?}", action, sn, error));
+        }
+        Ok(())
+    }
+
     async fn send_msg(&mut self, message: SendMsgMessage) -> Result<(), String> {
         log::info!("send_msg({:?})", message);
         let to_sn = &message.message_data.to_sn;
         let message_body = &message.message_data.message;
-        let session = { message.protocol_data.session.read().await.clone().unwrap() };
+        let session = current_session(&message.protocol_data).await?;
         let _msg_info = protocol::send_im(&session, to_sn, message_body)
             .await
             .map_err(|e| format!("Failed to send msg: {:?}", e))?;
         Ok(())
     }
 }
+
+async fn export_history(
+    mut tx: FdSender<SystemMessage>,
+    session: protocol::SessionInfo,
+    handle: Handle,
+    data: ExportHistoryMessageData,
+) {
+    let result =
+        export::export_history(&session, &data.sn, &data.path, data.format, data.since).await;
+
+    let notice = match &result {
+        Ok(count) => format!("Exported {} messages to {}", count, data.path.display()),
+        Err(export::Error::IoError(error)) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+            format!(
+                "Not exporting history, {} already exists",
+                data.path.display()
+            )
+        }
+        Err(_) => format!("Failed to export history to {}", data.path.display()),
+    };
+    if let Err(error) = result {
+        log::error!("Failed to export history: {:?}", error);
+    }
+
+    let sn = data.sn;
+    tx.handle_proxy(&handle)
+        .exec_no_return(move |plugin, protocol_data| {
+            let connection = &mut protocol_data.connection;
+            plugin.write_chat_system_message(connection, &sn, &notice);
+        })
+        .await;
+}
@@ -0,0 +1,63 @@
+use futures::future;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The background tasks of an account, such as join prompts and exports, which must not
+/// outlive the connection.
+#[derive(Debug, Default, Clone)]
+pub struct Tasks {
+    inner: Arc<Mutex<TasksInner>>,
+}
+
+#[derive(Debug, Default)]
+struct TasksInner {
+    next_id: u64,
+    abort_handles: HashMap<u64, future::AbortHandle>,
+    aborted: bool,
+}
+
+impl Tasks {
+    /// Run the task on the current thread, unless the tasks were already aborted.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let (task, abort_handle) = future::abortable(task);
+        let id = {
+            let mut inner = self.lock();
+            if inner.aborted {
+                log::info!("Not starting a task of a closed account");
+                return;
+            }
+            let id = inner.next_id;
+            inner.next_id += 1;
+            inner.abort_handles.insert(id, abort_handle);
+            id
+        };
+
+        let tasks = self.clone();
+        async_std::task::spawn_local(async move {
+            if task.await.is_err() {
+                log::info!("Task aborted");
+            }
+            tasks.lock().abort_handles.remove(&id);
+        });
+    }
+
+    /// Abort the running tasks, and any task spawned afterwards.
+    pub fn abort_all(&self) {
+        let mut inner = self.lock();
+        inner.aborted = true;
+        for (_, abort_handle) in inner.abort_handles.drain() {
+            abort_handle.abort();
+        }
+    }
+
+    // A task that panicked doesn't leave the map inconsistent.
+    fn lock(&self) -> MutexGuard<'_, TasksInner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -1,15 +1,28 @@
 use async_std::sync::{Arc, RwLock};
-use chat_info::{ChatInfo, ChatInfoVersion, PartialChatInfo};
+use cache::ChatCache;
+use chat_info::{ChatInfo, ChatInfoVersion, PartialChatInfo, PublicChatInfo, TopicChange};
+use icq::export::ExportFormat;
+use icq::history::HistoryLimit;
+use icq::protocol::{ProtocolOptions, SavedSession};
 use lazy_static::lazy_static;
-use messages::{AccountInfo, ICQSystemHandle, PurpleMessage, SystemMessage};
+use messages::{
+    AccountAction, AccountInfo, ExportHistoryMessageData, ICQSystemHandle, ModifyChatMessageData,
+    MoveBuddyMessageData, PurpleMessage, SearchMessagesMessageData, SystemMessage,
+};
 use purple::*;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::io::Read;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 
+mod cache;
 mod chat_info;
+mod date;
 mod icq;
 pub mod logging;
 mod messages;
@@ -29,12 +42,22 @@ lazy_static! {
     static ref ICON_FILE: CString = CString::new("icq").unwrap();
 }
 
+const ROOMLIST_MAX_ROOMS: u32 = 100;
+
 mod blist_node {
     pub const LAST_SEEN_TIMESTAMP: &str = "last_seen_timestamp";
+    pub const LAST_MESSAGE_ID: &str = "last_message_id";
+    pub const MUTED: &str = "muted";
 }
 
 mod commands {
     pub const HISTORY: &str = "history";
+    pub const LEAVE: &str = "leave";
+    pub const TITLE: &str = "title";
+    pub const RULES: &str = "rules";
+    pub const EXPORT: &str = "export";
+    pub const SEARCH: &str = "search";
+    pub const CONTEXT: &str = "context";
 }
 
 pub mod chat_states {
@@ -48,6 +71,7 @@ pub mod conv_data {
 
     pub const CHAT_INFO: &str = "chat_info";
     pub const HISTORY_INFO: &str = "history_info";
+    pub const TOPIC_CHANGE: &str = "topic_change";
     pub type HistoryInfoType = Rc<RefCell<HistoryInfo>>;
 }
 
@@ -57,6 +81,24 @@ pub struct HistoryInfo {
     pub oldest_message_timestamp: Option<i64>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsgSource {
+    /// Pushed by the server through the event stream.
+    Live,
+    /// Missed while offline and fetched on login.
+    CatchUp,
+    /// Explicitly requested by the user, such as with /history.
+    History,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuddyInfo {
+    pub sn: String,
+    pub alias: Option<String>,
+    pub group: String,
+    pub muted: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct MsgInfo {
     pub chat_sn: String,
@@ -65,13 +107,39 @@ pub struct MsgInfo {
     pub text: String,
     pub time: i64,
     pub message_id: String,
+    pub source: MsgSource,
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub struct AccountData {
-    phone_number: String,
+    username: String,
+    password: Option<String>,
     session_closed: AtomicBool,
+    needs_catch_up: AtomicBool,
+    last_seq_num: AtomicU32,
     session: RwLock<Option<icq::protocol::SessionInfo>>,
+    poller: RwLock<Option<icq::poller::PollerHandle>>,
+    tasks: icq::tasks::Tasks,
+    chat_cache: Mutex<ChatCache>,
+    options: ProtocolOptions,
+}
+
+// Every message to the system thread is logged with its account data, so the password is
+// left out, and so is the chat cache, which is too large.
+impl std::fmt::Debug for AccountData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccountData")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("session_closed", &self.session_closed)
+            .field("needs_catch_up", &self.needs_catch_up)
+            .field("last_seq_num", &self.last_seq_num)
+            .field("session", &self.session)
+            .field("poller", &self.poller)
+            .field("tasks", &self.tasks)
+            .field("options", &self.options)
+            .finish()
+    }
 }
 
 impl Drop for AccountData {
@@ -88,7 +156,16 @@ pub struct PurpleICQ {
     system: ICQSystemHandle,
     connections: purple::Connections<AccountDataBox>,
     input_handle: Option<u32>,
+    /// The room list being loaded for each account, by username.
+    roomlists: HashMap<String, purple::Roomlist>,
+    syncing_buddy_list: bool,
     history_command_handle: Option<PurpleCmdId>,
+    leave_command_handle: Option<PurpleCmdId>,
+    title_command_handle: Option<PurpleCmdId>,
+    rules_command_handle: Option<PurpleCmdId>,
+    export_command_handle: Option<PurpleCmdId>,
+    search_command_handle: Option<PurpleCmdId>,
+    context_command_handle: Option<PurpleCmdId>,
 }
 
 impl purple::PrplPlugin for PurpleICQ {
@@ -100,7 +177,15 @@ impl purple::PrplPlugin for PurpleICQ {
         Self {
             system,
             input_handle: None,
+            roomlists: HashMap::new(),
+            syncing_buddy_list: false,
             history_command_handle: None,
+            leave_command_handle: None,
+            title_command_handle: None,
+            rules_command_handle: None,
+            export_command_handle: None,
+            search_command_handle: None,
+            context_command_handle: None,
             connections: purple::Connections::new(),
         }
     }
@@ -115,8 +200,39 @@ impl purple::PrplPlugin for PurpleICQ {
             homepage: "https://github.com/Flared/purple-icq".into(),
         };
 
+        let defaults = ProtocolOptions::default();
         context
             .with_info(info)
+            .with_account_option(AccountOption::string(
+                "Language",
+                ProtocolOptions::LANGUAGE_OPTION_KEY,
+                &defaults.language,
+            ))
+            .with_account_option(AccountOption::string(
+                "Locale",
+                ProtocolOptions::LOCALE_OPTION_KEY,
+                &defaults.locale,
+            ))
+            .with_account_option(AccountOption::int(
+                "Chat members to list",
+                ProtocolOptions::MEMBER_LIMIT_OPTION_KEY,
+                defaults.member_limit as i32,
+            ))
+            .with_account_option(AccountOption::int(
+                "Messages per history request",
+                ProtocolOptions::HISTORY_PAGE_SIZE_OPTION_KEY,
+                defaults.history_page_size as i32,
+            ))
+            .with_account_option(AccountOption::int(
+                "Maximum event polling timeout (seconds)",
+                ProtocolOptions::FETCH_TIMEOUT_OPTION_KEY,
+                defaults.fetch_timeout.as_secs() as i32,
+            ))
+            .with_account_option(AccountOption::int(
+                "Session timeout (seconds)",
+                ProtocolOptions::SESSION_TIMEOUT_OPTION_KEY,
+                defaults.session_timeout as i32,
+            ))
             .enable_login()
             .enable_load()
             .enable_close()
@@ -126,21 +242,46 @@ impl purple::PrplPlugin for PurpleICQ {
             .enable_chat_leave()
             .enable_send_im()
             .enable_chat_send()
+            .enable_set_chat_topic()
+            .enable_chat_invite()
+            .enable_roomlist_get_list()
+            .enable_roomlist_cancel()
+            .enable_add_buddy()
+            .enable_remove_buddy()
+            .enable_alias_buddy()
+            .enable_group_buddy()
+            .enable_add_deny()
+            .enable_rem_deny()
             .enable_convo_closed()
             .enable_get_chat_name()
             .enable_get_cb_alias()
             .enable_list_icon()
             .enable_status_types()
+            .enable_blist_node_menu()
+            .enable_actions()
     }
 }
 
 impl purple::LoginHandler for PurpleICQ {
     fn login(&mut self, account: &mut Account) {
-        let phone_number = account.get_username().unwrap().into();
+        let username: String = account.get_username().unwrap().into();
+        let password = account
+            .get_password()
+            .filter(|password| !password.is_empty())
+            .map(Into::into);
+        let chat_cache = ChatCache::load(&username);
+        let options = read_protocol_options(account);
         let protocol_data: AccountDataBox = Arc::new(AccountData {
-            phone_number,
+            username,
+            password,
             session_closed: AtomicBool::new(false),
+            needs_catch_up: AtomicBool::new(true),
+            last_seq_num: AtomicU32::new(0),
             session: RwLock::new(None),
+            poller: RwLock::new(None),
+            tasks: Default::default(),
+            chat_cache: Mutex::new(chat_cache),
+            options,
         });
 
         // Safe as long as we remove the account in "close".
@@ -157,6 +298,55 @@ impl purple::LoginHandler for PurpleICQ {
             .unwrap();
     }
 }
+// Options out of range fall back on their defaults.
+fn read_protocol_options(account: &mut Account) -> ProtocolOptions {
+    let defaults = ProtocolOptions::default();
+    let read_string = |account: &mut Account, key: &str, default: String| {
+        Some(account.get_string(key, &default))
+            .filter(|value| !value.is_empty())
+            .unwrap_or(default)
+    };
+    let read_positive = |account: &mut Account, key: &str, default: u32| {
+        let value = account.get_int(key, default as i32);
+        if value > 0 {
+            value as u32
+        } else {
+            default
+        }
+    };
+    ProtocolOptions {
+        language: read_string(
+            account,
+            ProtocolOptions::LANGUAGE_OPTION_KEY,
+            defaults.language,
+        ),
+        locale: read_string(account, ProtocolOptions::LOCALE_OPTION_KEY, defaults.locale),
+        member_limit: read_positive(
+            account,
+            ProtocolOptions::MEMBER_LIMIT_OPTION_KEY,
+            defaults.member_limit,
+        ),
+        history_page_size: read_positive(
+            account,
+            ProtocolOptions::HISTORY_PAGE_SIZE_OPTION_KEY,
+            defaults.history_page_size,
+        ),
+        fetch_timeout: Duration::from_secs(
+            read_positive(
+                account,
+                ProtocolOptions::FETCH_TIMEOUT_OPTION_KEY,
+                defaults.fetch_timeout.as_secs() as u32,
+            )
+            .into(),
+        ),
+        session_timeout: read_positive(
+            account,
+            ProtocolOptions::SESSION_TIMEOUT_OPTION_KEY,
+            defaults.session_timeout,
+        ),
+    }
+}
+
 impl purple::CloseHandler for PurpleICQ {
     fn close(&mut self, connection: &mut Connection) {
         let handle = Handle::from(&mut *connection);
@@ -166,6 +356,30 @@ impl purple::CloseHandler for PurpleICQ {
                     .data
                     .session_closed
                     .store(true, Ordering::Relaxed);
+                protocol_data.data.tasks.abort_all();
+
+                // Keep the session to reconnect or to resume it after a restart, which leave
+                // the account online. Going offline or disabling the account ends it.
+                // The poller stops saving once the session is closed, and account
+                // requests queued after this are dropped with the connection.
+                let mut account = connection.get_account();
+                let end_session =
+                    !account.is_enabled() || account.is_status_active(&status::OFFLINE_ID);
+                if end_session {
+                    if let Err(error) = account.set_settings(&SavedSession::default()) {
+                        log::error!("Failed to clear the saved session: {:?}", error);
+                    }
+                }
+
+                // The system keeps the account data alive until the poller has exited.
+                self.system
+                    .tx
+                    .try_send(PurpleMessage::logout(
+                        handle.clone(),
+                        protocol_data.data.clone(),
+                        end_session,
+                    ))
+                    .unwrap();
                 self.connections.remove(*connection);
             }
             None => {
@@ -201,8 +415,25 @@ impl purple::LoadHandler for PurpleICQ {
             purple::PurpleInputCondition::PURPLE_INPUT_READ,
         ));
 
-        self.history_command_handle =
-            Some(self.enable_command(commands::HISTORY, "w", "history &lt;count&gt;"));
+        self.history_command_handle = Some(self.enable_command(
+            commands::HISTORY,
+            "s",
+            "history &lt;count&gt; | history since &lt;YYYY-MM-DD&gt;",
+        ));
+        self.leave_command_handle = Some(self.enable_command(commands::LEAVE, "", "leave"));
+        self.title_command_handle =
+            Some(self.enable_command(commands::TITLE, "s", "title &lt;title&gt;"));
+        self.rules_command_handle =
+            Some(self.enable_command(commands::RULES, "s", "rules &lt;rules&gt;"));
+        self.export_command_handle = Some(self.enable_command(
+            commands::EXPORT,
+            "s",
+            "export [--format jsonl|csv] [--since &lt;YYYY-MM-DD&gt;] &lt;file&gt;",
+        ));
+        self.search_command_handle =
+            Some(self.enable_command(commands::SEARCH, "s", "search [--all] &lt;terms&gt;"));
+        self.context_command_handle =
+            Some(self.enable_command(commands::CONTEXT, "w", "context &lt;msg_id&gt;"));
         true
     }
 }
@@ -309,6 +540,43 @@ impl purple::ChatLeaveHandler for PurpleICQ {
     }
 }
 
+impl purple::BlistNodeMenuHandler for PurpleICQ {
+    fn blist_node_menu(
+        &mut self,
+        node: &mut purple::BlistNode,
+    ) -> Vec<purple::BlistNodeAction<Self>> {
+        let mut actions = Vec::new();
+        let mut can_mute = node.as_buddy().is_some();
+        if let Some(mut chat) = node.as_chat() {
+            if let Some(chat_states::JOINED) = chat.get_components().lookup(&chat_info::STATE) {
+                actions.push(purple::BlistNodeAction::new(
+                    "Leave chat",
+                    Self::blist_leave_chat,
+                ));
+                can_mute = true;
+            }
+        }
+        if can_mute {
+            if node.get_bool(&blist_node::MUTED) {
+                actions.push(purple::BlistNodeAction::new("Unmute", Self::blist_unmute));
+            } else {
+                actions.push(purple::BlistNodeAction::new("Mute", Self::blist_mute));
+            }
+        }
+        actions
+    }
+}
+
+impl purple::ActionsHandler for PurpleICQ {
+    fn actions(&mut self) -> Vec<purple::PluginAction<Self>> {
+        vec![
+            purple::PluginAction::new("Resync buddy list and chats", Self::action_resync),
+            purple::PluginAction::new("Show my account info", Self::action_show_account_info),
+            purple::PluginAction::new("Forget saved login", Self::action_forget_login),
+        ]
+    }
+}
+
 impl purple::ConvoClosedHandler for PurpleICQ {
     fn convo_closed(&mut self, _connection: &mut Connection, who: Option<&str>) {
         log::info!("Convo closed: {:?}", who)
@@ -391,6 +659,265 @@ impl purple::ChatSendHandler for PurpleICQ {
     }
 }
 
+impl purple::SetChatTopicHandler for PurpleICQ {
+    fn set_chat_topic(&mut self, connection: &mut Connection, id: i32, topic: Option<&str>) {
+        log::info!("Set chat topic: {}, {:?}", id, topic);
+        let sn = match Conversation::find(connection, id).and_then(|mut c| {
+            unsafe { c.get_data::<ChatInfo>(conv_data::CHAT_INFO) }.map(|info| info.sn.clone())
+        }) {
+            Some(sn) => sn,
+            None => {
+                log::error!("SN not found");
+                return;
+            }
+        };
+
+        self.modify_chat(
+            connection,
+            ModifyChatMessageData {
+                sn,
+                name: None,
+                about: Some(topic.unwrap_or("").into()),
+                rules: None,
+            },
+        );
+    }
+}
+
+impl purple::ChatInviteHandler for PurpleICQ {
+    fn chat_invite(
+        &mut self,
+        connection: &mut Connection,
+        id: i32,
+        _message: Option<&str>,
+        who: &str,
+    ) {
+        log::info!("Chat invite: {}, {}", id, who);
+        let sn = match Conversation::find(connection, id).and_then(|mut c| {
+            unsafe { c.get_data::<ChatInfo>(conv_data::CHAT_INFO) }.map(|info| info.sn.clone())
+        }) {
+            Some(sn) => sn,
+            None => {
+                log::error!("SN not found");
+                return;
+            }
+        };
+
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried inviting to chat on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::invite_to_chat(
+                handle,
+                protocol_data.data.clone(),
+                sn,
+                who.into(),
+            ))
+            .unwrap();
+    }
+}
+
+impl purple::RoomlistGetListHandler for PurpleICQ {
+    fn roomlist_get_list(&mut self, connection: &mut Connection) -> Option<purple::Roomlist> {
+        log::info!("Room list requested");
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried getting room list on closed connection");
+
+        let mut roomlist = purple::Roomlist::new(&mut connection.get_account());
+        roomlist.set_fields(vec![
+            purple::prpl::RoomlistField {
+                field_type: PurpleRoomlistFieldType::PURPLE_ROOMLIST_FIELD_STRING,
+                label: &chat_info::STAMP_NAME,
+                name: &chat_info::SN,
+                hidden: true,
+            },
+            purple::prpl::RoomlistField {
+                field_type: PurpleRoomlistFieldType::PURPLE_ROOMLIST_FIELD_INT,
+                label: &chat_info::MEMBERS_COUNT_NAME,
+                name: &chat_info::MEMBERS_COUNT,
+                hidden: false,
+            },
+            purple::prpl::RoomlistField {
+                field_type: PurpleRoomlistFieldType::PURPLE_ROOMLIST_FIELD_STRING,
+                label: &chat_info::ABOUT_NAME,
+                name: &chat_info::ABOUT,
+                hidden: false,
+            },
+        ]);
+        roomlist.set_in_progress(true);
+
+        // One room list is loaded at a time per account, a new request replaces the previous one.
+        let protocol_data = protocol_data.data.clone();
+        if let Some(mut previous) = self
+            .roomlists
+            .insert(protocol_data.username.clone(), roomlist.clone())
+        {
+            previous.set_in_progress(false);
+        }
+
+        let tx = self.system.tx.clone();
+        let mut cancelled_roomlist = roomlist.clone();
+        connection.get_account().request_input(
+            Some("Public chats"),
+            Some("Search public chats"),
+            Some("Leave empty to browse the chat catalog."),
+            None,
+            false,
+            false,
+            None,
+            "Search",
+            "Cancel",
+            move |keyword| match keyword {
+                Some(keyword) => {
+                    log::info!("Searching chats: {}", keyword);
+                    tx.try_send(PurpleMessage::search_chats(
+                        handle,
+                        protocol_data,
+                        keyword.into_owned(),
+                        ROOMLIST_MAX_ROOMS,
+                    ))
+                    .unwrap();
+                }
+                None => cancelled_roomlist.set_in_progress(false),
+            },
+            None,
+        );
+        Some(roomlist)
+    }
+}
+
+impl purple::RoomlistCancelHandler for PurpleICQ {
+    fn roomlist_cancel(&mut self, roomlist: &mut purple::Roomlist) {
+        log::info!("Room list cancelled");
+        roomlist.set_in_progress(false);
+        if let Some(username) = roomlist.get_account().get_username() {
+            self.roomlists.remove(&*username);
+        }
+    }
+}
+
+impl purple::AddBuddyHandler for PurpleICQ {
+    fn add_buddy(
+        &mut self,
+        connection: &mut Connection,
+        buddy: &mut purple::Buddy,
+        group: &mut purple::Group,
+    ) {
+        log::info!("Add buddy: {}", buddy.get_name());
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried adding buddy on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::add_buddy(
+                handle,
+                protocol_data.data.clone(),
+                buddy.get_name().to_string(),
+                group.get_name().to_string(),
+            ))
+            .unwrap();
+    }
+}
+
+impl purple::RemoveBuddyHandler for PurpleICQ {
+    fn remove_buddy(
+        &mut self,
+        connection: &mut Connection,
+        buddy: &mut purple::Buddy,
+        _group: &mut purple::Group,
+    ) {
+        log::info!("Remove buddy: {}", buddy.get_name());
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried removing buddy on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::remove_buddy(
+                handle,
+                protocol_data.data.clone(),
+                buddy.get_name().to_string(),
+            ))
+            .unwrap();
+    }
+}
+
+impl purple::AliasBuddyHandler for PurpleICQ {
+    fn alias_buddy(&mut self, connection: &mut Connection, who: &str, alias: Option<&str>) {
+        log::info!("Alias buddy: {} -> {:?}", who, alias);
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried aliasing buddy on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::alias_buddy(
+                handle,
+                protocol_data.data.clone(),
+                who.to_string(),
+                alias.unwrap_or_default().to_string(),
+            ))
+            .unwrap();
+    }
+}
+
+impl purple::GroupBuddyHandler for PurpleICQ {
+    fn group_buddy(
+        &mut self,
+        connection: &mut Connection,
+        who: &str,
+        old_group: &str,
+        new_group: &str,
+    ) {
+        // Moves made while applying the server's buddy list are already on the server.
+        if self.syncing_buddy_list {
+            return;
+        }
+        log::info!("Group buddy: {} {} -> {}", who, old_group, new_group);
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried moving buddy on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::move_buddy(
+                handle,
+                protocol_data.data.clone(),
+                MoveBuddyMessageData {
+                    sn: who.to_string(),
+                    old_group: old_group.to_string(),
+                    new_group: new_group.to_string(),
+                },
+            ))
+            .unwrap();
+    }
+}
+
+impl purple::AddDenyHandler for PurpleICQ {
+    fn add_deny(&mut self, connection: &mut Connection, name: &str) {
+        log::info!("Add deny: {}", name);
+        self.set_blocked(connection, name, true);
+    }
+}
+
+impl purple::RemDenyHandler for PurpleICQ {
+    fn rem_deny(&mut self, connection: &mut Connection, name: &str) {
+        log::info!("Remove deny: {}", name);
+        self.set_blocked(connection, name, false);
+    }
+}
+
 impl purple::InputHandler for PurpleICQ {
     fn input(&mut self, _fd: i32, _cond: purple::PurpleInputCondition) {
         log::debug!("Input");
@@ -432,6 +959,13 @@ impl purple::CommandHandler for PurpleICQ {
         );
         match command {
             commands::HISTORY => self.command_history(conversation, args),
+            commands::LEAVE => self.command_leave(conversation),
+            commands::EXPORT => self.command_export(conversation, args),
+            commands::SEARCH => self.command_search(conversation, args),
+            commands::CONTEXT => self.command_context(conversation, args),
+            commands::TITLE | commands::RULES => {
+                self.command_modify_chat(conversation, command, args)
+            }
             _ => {
                 log::error!("Unknown command: {}", command);
                 PurpleCmdRet::PURPLE_CMD_RET_FAILED
@@ -444,22 +978,21 @@ impl PurpleICQ {
     fn command_history(&mut self, conversation: &mut Conversation, args: &[&str]) -> PurpleCmdRet {
         log::debug!("command_history");
 
-        if args.len() != 1 {
-            log::error!(
-                "command_history: Unsupported number of args. Got {}",
-                args.len()
-            );
-            return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
-        }
-        let count = {
-            let input = match args[0].parse::<u32>() {
-                Ok(count) => count,
-                Err(_) => {
-                    log::error!("command_history: Could not parse count: {}", args[0]);
-                    return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
-                }
-            };
-            0 - input as i32
+        let args = args
+            .first()
+            .map(|args| args.split_whitespace().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let limit = match args.as_slice() {
+            ["since", since] => date::parse_date(since).map(HistoryLimit::Since),
+            [count] => count.parse::<usize>().ok().map(HistoryLimit::Count),
+            _ => None,
+        };
+        let limit = match limit {
+            Some(limit) => limit,
+            None => {
+                log::error!("command_history: Could not parse args: {:?}", args);
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
         };
 
         let sn = match conversation.get_name() {
@@ -470,25 +1003,11 @@ impl PurpleICQ {
             }
         };
 
-        let from_msg_id = {
-            match unsafe {
-                conversation.get_data::<conv_data::HistoryInfoType>(conv_data::HISTORY_INFO)
-            } {
-                Some(history_info) => {
-                    let history_info = history_info.borrow_mut();
-                    match &history_info.oldest_message_id {
-                        Some(oldest_message_id) => oldest_message_id.clone(),
-                        None => {
-                            return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
-                        }
-                    }
-                }
-                None => {
-                    log::error!("command_history: Can't find message id");
-                    return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
-                }
-            }
-        };
+        // Continue from the oldest displayed message, or from the newest one in a
+        // conversation that doesn't show any message yet.
+        let from_msg_id =
+            unsafe { conversation.get_data::<conv_data::HistoryInfoType>(conv_data::HISTORY_INFO) }
+                .and_then(|history_info| history_info.borrow().oldest_message_id.clone());
 
         let handle = Handle::from(&mut conversation.get_connection());
 
@@ -504,79 +1023,475 @@ impl PurpleICQ {
                 protocol_data.data.clone(),
                 sn,
                 from_msg_id,
-                count,
+                limit,
             ))
             .unwrap();
 
         PurpleCmdRet::PURPLE_CMD_RET_OK
     }
 
-    fn process_message(&mut self, message: SystemMessage) {
-        match message {
-            SystemMessage::ExecAccount { handle, function } => {
-                self.connections
-                    .get(handle)
-                    .map(|protocol_data| function(&mut protocol_data.account))
-                    .or_else(|| {
-                        log::warn!("The account connection has been closed");
-                        None
-                    });
-            }
-            SystemMessage::ExecConnection { handle, function } => {
-                self.connections
-                    .get(handle)
-                    .map(|protocol_data| function(&mut protocol_data.connection))
-                    .or_else(|| {
-                        log::warn!("The account connection has been closed");
-                        None
-                    });
+    fn command_export(&mut self, conversation: &mut Conversation, args: &[&str]) -> PurpleCmdRet {
+        log::debug!("command_export");
+
+        let args = args.first().copied().unwrap_or_default();
+        let (path, format, since) = match parse_export_args(args) {
+            Some(parsed) => parsed,
+            None => {
+                log::error!("command_export: Could not parse args: {:?}", args);
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
             }
-            SystemMessage::ExecHandle { handle, function } => {
-                self.connections
-                    .get(handle)
-                    .map(|mut protocol_data| function(self, &mut protocol_data))
-                    .or_else(|| {
-                        log::warn!("The account connection has been closed");
-                        None
-                    });
+        };
+
+        let sn = match conversation.get_name() {
+            Some(name) => name.to_string(),
+            None => {
+                log::error!("command_export: SN not found");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
             }
-            SystemMessage::FlushLogs => logging::flush(),
-        }
+        };
+
+        let handle = Handle::from(&mut conversation.get_connection());
+
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried exporting history on closed connection");
+
+        self.system
+            .tx
+            .try_send(PurpleMessage::export_history(
+                handle,
+                protocol_data.data.clone(),
+                ExportHistoryMessageData {
+                    sn,
+                    path,
+                    format,
+                    since,
+                },
+            ))
+            .unwrap();
+
+        PurpleCmdRet::PURPLE_CMD_RET_OK
     }
 
-    pub fn serv_got_chat_in(&mut self, connection: &mut Connection, msg_info: MsgInfo) {
-        match purple::Chat::find(&mut connection.get_account(), &msg_info.chat_sn) {
-            Some(mut chat) => {
-                // Get the chat and the last seen timestamp.
-                let mut node = chat.as_blist_node();
-                let last_timestamp: i64 = node
-                    .get_string(&blist_node::LAST_SEEN_TIMESTAMP)
-                    .and_then(|t| t.parse::<i64>().ok())
-                    .unwrap_or(0);
-                let new_timestamp = msg_info.time;
+    fn command_search(&mut self, conversation: &mut Conversation, args: &[&str]) -> PurpleCmdRet {
+        log::debug!("command_search");
 
-                // Only trigger conversation_joined if this is a new message.
-                let conversation = {
-                    if new_timestamp > last_timestamp {
-                        node.set_string(
-                            &blist_node::LAST_SEEN_TIMESTAMP,
-                            &new_timestamp.to_string(),
-                        );
-                        Some(self.conversation_joined(
-                            connection,
-                            &PartialChatInfo {
-                                sn: msg_info.chat_sn.clone(),
-                                title: msg_info.chat_sn.clone(),
-                                ..Default::default()
-                            },
-                        ))
-                    } else {
-                        None
-                    }
-                };
+        let args = args.first().map(|args| args.trim()).unwrap_or_default();
+        let (all_chats, keyword) = match args.strip_prefix("--all") {
+            Some(keyword) => (true, keyword.trim()),
+            None => (false, args),
+        };
+        if keyword.is_empty() {
+            log::error!("command_search: Missing search terms");
+            return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+        }
 
-                // Get the conversation and set the oldest *displayed* messageId.
-                // This is the oldest message that the user can see in the chat window.
+        let sn = match conversation.get_name() {
+            Some(name) => name.to_string(),
+            None => {
+                log::error!("command_search: SN not found");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        let handle = Handle::from(&mut conversation.get_connection());
+
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried searching messages on closed connection");
+
+        self.system
+            .tx
+            .try_send(PurpleMessage::search_messages(
+                handle,
+                protocol_data.data.clone(),
+                SearchMessagesMessageData {
+                    sn,
+                    keyword: keyword.to_string(),
+                    all_chats,
+                },
+            ))
+            .unwrap();
+
+        PurpleCmdRet::PURPLE_CMD_RET_OK
+    }
+
+    fn command_context(&mut self, conversation: &mut Conversation, args: &[&str]) -> PurpleCmdRet {
+        log::debug!("command_context");
+
+        let msg_id = match args.first() {
+            Some(msg_id) if !msg_id.is_empty() => msg_id.to_string(),
+            _ => {
+                log::error!("command_context: Missing msg_id");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        let sn = match conversation.get_name() {
+            Some(name) => name.to_string(),
+            None => {
+                log::error!("command_context: SN not found");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        let handle = Handle::from(&mut conversation.get_connection());
+
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried fetching context on closed connection");
+
+        self.system
+            .tx
+            .try_send(PurpleMessage::fetch_context(
+                handle,
+                protocol_data.data.clone(),
+                sn,
+                msg_id,
+            ))
+            .unwrap();
+
+        PurpleCmdRet::PURPLE_CMD_RET_OK
+    }
+
+    fn command_leave(&mut self, conversation: &mut Conversation) -> PurpleCmdRet {
+        log::debug!("command_leave");
+
+        let info = match unsafe { conversation.get_data::<ChatInfo>(conv_data::CHAT_INFO) } {
+            Some(info) => info.as_partial(),
+            None => {
+                log::error!("command_leave: Chat info not found");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        self.confirm_leave_chat(&mut conversation.get_connection(), info);
+        PurpleCmdRet::PURPLE_CMD_RET_OK
+    }
+
+    fn command_modify_chat(
+        &mut self,
+        conversation: &mut Conversation,
+        command: &str,
+        args: &[&str],
+    ) -> PurpleCmdRet {
+        log::debug!("command_modify_chat");
+
+        let value = match args.first() {
+            Some(value) => value.to_string(),
+            None => {
+                log::error!("command_modify_chat: Missing value");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        let sn = match unsafe { conversation.get_data::<ChatInfo>(conv_data::CHAT_INFO) } {
+            Some(info) => info.sn.clone(),
+            None => {
+                log::error!("command_modify_chat: SN not found");
+                return PurpleCmdRet::PURPLE_CMD_RET_FAILED;
+            }
+        };
+
+        let (name, rules) = match command {
+            commands::TITLE => (Some(value), None),
+            _ => (None, Some(value)),
+        };
+        self.modify_chat(
+            &mut conversation.get_connection(),
+            ModifyChatMessageData {
+                sn,
+                name,
+                about: None,
+                rules,
+            },
+        );
+        PurpleCmdRet::PURPLE_CMD_RET_OK
+    }
+
+    fn set_blocked(&mut self, connection: &mut Connection, sn: &str, blocked: bool) {
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried blocking user on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::set_blocked(
+                handle,
+                protocol_data.data.clone(),
+                sn.to_string(),
+                blocked,
+            ))
+            .unwrap();
+    }
+
+    fn modify_chat(&mut self, connection: &mut Connection, message_data: ModifyChatMessageData) {
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried modifying chat on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::modify_chat(
+                handle,
+                protocol_data.data.clone(),
+                message_data,
+            ))
+            .unwrap();
+    }
+
+    fn blist_leave_chat(&mut self, node: &mut purple::BlistNode) {
+        let mut chat = match node.as_chat() {
+            Some(chat) => chat,
+            None => return,
+        };
+        let info = match PartialChatInfo::from_hashtable(chat.get_components()) {
+            Some(info) => info,
+            None => {
+                log::error!("blist_leave_chat: Unable to load chat info");
+                return;
+            }
+        };
+        match chat.get_account().get_connection() {
+            Some(mut connection) => self.confirm_leave_chat(&mut connection, info),
+            None => log::warn!("Tried leaving chat on disconnected account"),
+        }
+    }
+
+    fn action_resync(&mut self, connection: &mut Connection) {
+        self.account_action(connection, AccountAction::Resync);
+    }
+
+    fn action_show_account_info(&mut self, connection: &mut Connection) {
+        self.account_action(connection, AccountAction::ShowAccountInfo);
+    }
+
+    fn action_forget_login(&mut self, connection: &mut Connection) {
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried forgetting login on closed connection")
+            .data
+            .clone();
+        let tx = self.system.tx.clone();
+        let username = protocol_data.username.clone();
+
+        connection.get_account().request_action(
+            Some("Forget saved login"),
+            Some(&format!("Forget the saved login of {}?", username)),
+            Some("You will be disconnected, and logging in again will ask for a new code or your password."),
+            1,
+            None,
+            &["Forget", "Cancel"],
+            move |action| {
+                if action == 0 {
+                    tx.try_send(PurpleMessage::account_action(
+                        handle,
+                        protocol_data,
+                        AccountAction::ForgetLogin,
+                    ))
+                    .unwrap();
+                }
+            },
+        );
+    }
+
+    fn account_action(&mut self, connection: &mut Connection, action: AccountAction) {
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried running account action on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::account_action(
+                handle,
+                protocol_data.data.clone(),
+                action,
+            ))
+            .unwrap();
+    }
+
+    fn blist_mute(&mut self, node: &mut purple::BlistNode) {
+        self.blist_set_muted(node, true);
+    }
+
+    fn blist_unmute(&mut self, node: &mut purple::BlistNode) {
+        self.blist_set_muted(node, false);
+    }
+
+    fn blist_set_muted(&mut self, node: &mut purple::BlistNode, muted: bool) {
+        let (sn, mut account) = if let Some(mut chat) = node.as_chat() {
+            match PartialChatInfo::from_hashtable(chat.get_components()) {
+                Some(info) => (info.sn, chat.get_account()),
+                None => {
+                    log::error!("blist_set_muted: Unable to load chat info");
+                    return;
+                }
+            }
+        } else if let Some(mut buddy) = node.as_buddy() {
+            (buddy.get_name().to_string(), buddy.get_account())
+        } else {
+            return;
+        };
+
+        let mut connection = match account.get_connection() {
+            Some(connection) => connection,
+            None => {
+                log::warn!("Tried muting on disconnected account");
+                return;
+            }
+        };
+
+        // Applied right away, the next buddy list from the server confirms or reverts it.
+        node.set_bool(&blist_node::MUTED, muted);
+
+        let handle = Handle::from(&mut connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried muting on closed connection");
+        self.system
+            .tx
+            .try_send(PurpleMessage::set_muted(
+                handle,
+                protocol_data.data.clone(),
+                sn,
+                muted,
+            ))
+            .unwrap();
+    }
+
+    fn confirm_leave_chat(&mut self, connection: &mut Connection, info: PartialChatInfo) {
+        let handle = Handle::from(&mut *connection);
+        let protocol_data = self
+            .connections
+            .get(&handle)
+            .expect("Tried leaving chat on closed connection")
+            .data
+            .clone();
+        let tx = self.system.tx.clone();
+
+        connection.get_account().request_action(
+            Some("Leave chat"),
+            Some(&format!("Leave {}?", info.title)),
+            Some("You will stop receiving messages from this chat and it will be removed from your buddy list."),
+            1,
+            None,
+            &["Leave", "Cancel"],
+            move |action| {
+                if action == 0 {
+                    tx.try_send(PurpleMessage::leave_chat(handle, protocol_data, info.sn))
+                        .unwrap();
+                }
+            },
+        );
+    }
+
+    fn process_message(&mut self, message: SystemMessage) {
+        match message {
+            SystemMessage::ExecAccount { handle, function } => {
+                self.connections
+                    .get(handle)
+                    .map(|protocol_data| function(&mut protocol_data.account))
+                    .or_else(|| {
+                        log::warn!("The account connection has been closed");
+                        None
+                    });
+            }
+            SystemMessage::ExecConnection { handle, function } => {
+                self.connections
+                    .get(handle)
+                    .map(|protocol_data| function(&mut protocol_data.connection))
+                    .or_else(|| {
+                        log::warn!("The account connection has been closed");
+                        None
+                    });
+            }
+            SystemMessage::ExecHandle { handle, function } => {
+                self.connections
+                    .get(handle)
+                    .map(|mut protocol_data| function(self, &mut protocol_data))
+                    .or_else(|| {
+                        log::warn!("The account connection has been closed");
+                        None
+                    });
+            }
+            SystemMessage::FlushLogs => logging::flush(),
+        }
+    }
+
+    pub fn serv_got_chat_in(&mut self, connection: &mut Connection, msg_info: MsgInfo) {
+        let mut muted = false;
+        match purple::Chat::find(&mut connection.get_account(), &msg_info.chat_sn) {
+            Some(mut chat) => {
+                let mut node = chat.as_blist_node();
+                muted = node.get_bool(&blist_node::MUTED);
+
+                // Skip messages that were already delivered, unless the user asked for them.
+                let message_id = match msg_info.source {
+                    MsgSource::History => None,
+                    _ => msg_info.message_id.parse::<u64>().ok(),
+                };
+                if let Some(message_id) = message_id {
+                    let last_message_id = node
+                        .get_string(&blist_node::LAST_MESSAGE_ID)
+                        .and_then(|id| id.parse::<u64>().ok())
+                        .unwrap_or(0);
+                    if message_id <= last_message_id {
+                        log::debug!("Skipping delivered message {}", msg_info.message_id);
+                        return;
+                    }
+                }
+
+                // Get the chat and the last seen timestamp.
+                let last_timestamp: i64 = node
+                    .get_string(&blist_node::LAST_SEEN_TIMESTAMP)
+                    .and_then(|t| t.parse::<i64>().ok())
+                    .unwrap_or(0);
+                let new_timestamp = msg_info.time;
+
+                // Only trigger conversation_joined if this is a new message. Older messages,
+                // such as the ones fetched with /history, go to the existing conversation.
+                // Muted chats never open or present a conversation.
+                let conversation = {
+                    if new_timestamp > last_timestamp && !muted {
+                        Some(self.conversation_joined(
+                            connection,
+                            &PartialChatInfo {
+                                sn: msg_info.chat_sn.clone(),
+                                title: msg_info.chat_sn.clone(),
+                                ..Default::default()
+                            },
+                        ))
+                    } else {
+                        connection
+                            .get_account()
+                            .find_chat_conversation(&msg_info.chat_sn)
+                    }
+                };
+                // Not written, so catch-up fetches it again once the chat is unmuted.
+                if muted && conversation.is_none() {
+                    log::debug!("Not opening muted chat {}", msg_info.chat_sn);
+                    return;
+                }
+
+                // The message is written below, so it counts as delivered.
+                if message_id.is_some() {
+                    node.set_string(&blist_node::LAST_MESSAGE_ID, &msg_info.message_id);
+                }
+                if new_timestamp > last_timestamp {
+                    node.set_string(&blist_node::LAST_SEEN_TIMESTAMP, &new_timestamp.to_string());
+                }
+
+                // Get the conversation and set the oldest *displayed* messageId.
+                // This is the oldest message that the user can see in the chat window.
                 //
                 // If there is no conversation yet, that is okay. It means that we haven't
                 // seen new messages yet.
@@ -604,17 +1519,13 @@ impl PurpleICQ {
                     };
                     let mut history_info = history_info.borrow_mut();
 
-                    match history_info.oldest_message_timestamp {
-                        None => {
-                            history_info.oldest_message_id = Some(msg_info.message_id.clone());
-                            history_info.oldest_message_timestamp = Some(msg_info.time);
-                        }
-                        Some(existing_timestamp) => {
-                            if msg_info.time < existing_timestamp {
-                                history_info.oldest_message_id = Some(msg_info.message_id.clone());
-                                history_info.oldest_message_timestamp = Some(msg_info.time);
-                            }
-                        }
+                    let is_oldest = history_info
+                        .oldest_message_timestamp
+                        .map(|existing_timestamp| msg_info.time < existing_timestamp)
+                        .unwrap_or(true);
+                    if is_oldest {
+                        history_info.oldest_message_id = Some(msg_info.message_id.clone());
+                        history_info.oldest_message_timestamp = Some(msg_info.time);
                     }
                 }
             }
@@ -627,9 +1538,17 @@ impl PurpleICQ {
             }
         }
 
-        connection.serv_got_chat_in(
+        // Muted chats are shown like caught up messages, which don't notify.
+        let flags = match msg_info.source {
+            MsgSource::Live if !muted => PurpleMessageFlags::PURPLE_MESSAGE_RECV,
+            MsgSource::Live | MsgSource::CatchUp | MsgSource::History => {
+                PurpleMessageFlags::PURPLE_MESSAGE_RECV | PurpleMessageFlags::PURPLE_MESSAGE_DELAYED
+            }
+        };
+        connection.serv_got_chat_in_with_flags(
             &msg_info.chat_sn,
             &msg_info.author_sn,
+            flags,
             &msg_info.text,
             msg_info.time,
         );
@@ -644,6 +1563,180 @@ impl PurpleICQ {
         };
     }
 
+    /// Replace the account's buddies with the server's buddy list.
+    pub fn buddy_list_loaded(&mut self, connection: &mut Connection, buddies: &[BuddyInfo]) {
+        log::info!("Buddy list loaded: {} buddies", buddies.len());
+        let mut account = connection.get_account();
+        self.syncing_buddy_list = true;
+
+        for info in buddies {
+            let mut group = self.get_or_create_group(Some(&info.group));
+            let mut buddy = match purple::Buddy::find(&mut account, &info.sn) {
+                Some(mut buddy) => {
+                    let is_in_group = match buddy.get_group() {
+                        Some(mut buddy_group) => buddy_group.get_name().eq(&info.group),
+                        None => false,
+                    };
+                    if !is_in_group {
+                        buddy.add_to_blist(&mut group, None);
+                    }
+                    buddy
+                }
+                None => {
+                    let mut buddy = purple::Buddy::new(&mut account, &info.sn, None);
+                    buddy.add_to_blist(&mut group, None);
+                    buddy
+                }
+            };
+            if let Some(alias) = &info.alias {
+                buddy.set_alias(alias);
+            }
+            buddy
+                .as_blist_node()
+                .set_bool(&blist_node::MUTED, info.muted);
+        }
+
+        for mut buddy in account.get_buddies() {
+            if !buddies.iter().any(|info| info.sn == buddy.get_name()) {
+                log::info!("Buddy removed on the server: {}", buddy.get_name());
+                buddy.remove_from_blist();
+            }
+        }
+
+        self.syncing_buddy_list = false;
+    }
+
+    /// Replace the account's privacy lists with the server's permit and deny lists.
+    pub fn privacy_lists_loaded(
+        &mut self,
+        connection: &mut Connection,
+        privacy_type: Option<PurplePrivacyType>,
+        permit: &[String],
+        deny: &[String],
+    ) {
+        log::info!(
+            "Privacy lists loaded: {:?}, {} permitted, {} denied",
+            privacy_type,
+            permit.len(),
+            deny.len()
+        );
+        let mut account = connection.get_account();
+        if let Some(privacy_type) = privacy_type {
+            account.set_privacy_type(privacy_type);
+        }
+
+        // Local only changes, so that they aren't sent back to the server.
+        for who in account.get_permit_list() {
+            if !permit.contains(&who) {
+                account.privacy_permit_remove(&who, true);
+            }
+        }
+        for who in permit {
+            account.privacy_permit_add(who, true);
+        }
+        for who in account.get_deny_list() {
+            if !deny.contains(&who) {
+                account.privacy_deny_remove(&who, true);
+            }
+        }
+        for who in deny {
+            account.privacy_deny_add(who, true);
+        }
+    }
+
+    pub fn mute_changed(&mut self, connection: &mut Connection, sn: &str, muted: bool) {
+        let mut account = connection.get_account();
+        if let Some(mut chat) = purple::Chat::find(&mut account, sn) {
+            chat.as_blist_node().set_bool(&blist_node::MUTED, muted);
+        } else if let Some(mut buddy) = purple::Buddy::find(&mut account, sn) {
+            buddy.as_blist_node().set_bool(&blist_node::MUTED, muted);
+        }
+    }
+
+    pub fn chat_invited(&mut self, connection: &mut Connection, info: &ChatInfo, inviter_sn: &str) {
+        log::info!("chat invited: {} by {}", info.sn, inviter_sn);
+        let stamp = match &info.stamp {
+            Some(stamp) => stamp,
+            None => {
+                log::error!("Invited to chat without stamp: {}", info.sn);
+                return;
+            }
+        };
+
+        // Accepting the invite goes through `join_chat` with the stamp as the chat name.
+        let mut components = purple::StrHashTable::default();
+        components.insert(&chat_info::SN, stamp);
+        connection.serv_got_chat_invite(&info.title, inviter_sn, info.about.as_deref(), components);
+    }
+
+    pub fn roomlist_loaded(&mut self, username: &str, rooms: &[PublicChatInfo]) {
+        log::info!("Room list loaded: {} rooms", rooms.len());
+        let mut roomlist = match self.roomlists.remove(username) {
+            Some(roomlist) => roomlist,
+            None => {
+                log::warn!("Loaded room list for no request");
+                return;
+            }
+        };
+
+        // Joining a room passes its fields to `join_chat`, where the stamp is the chat name.
+        for info in rooms {
+            let mut room = purple::RoomlistRoom::new(
+                PurpleRoomlistRoomType::PURPLE_ROOMLIST_ROOMTYPE_ROOM,
+                &info.title,
+                None,
+            );
+            roomlist.room_add_field_string(&mut room, &info.stamp);
+            roomlist.room_add_field_int(&mut room, info.members_count as i32);
+            roomlist.room_add_field_string(&mut room, info.about.as_deref().unwrap_or(""));
+            roomlist.room_add(room);
+        }
+        roomlist.set_in_progress(false);
+    }
+
+    fn with_chat_cache<F, T>(&mut self, connection: &mut Connection, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut ChatCache) -> T,
+    {
+        let handle = Handle::from(&mut *connection);
+        match self.connections.get(&handle) {
+            Some(protocol_data) => match protocol_data.data.chat_cache.lock() {
+                Ok(mut cache) => Some(f(&mut cache)),
+                Err(_) => {
+                    log::error!("Chat cache lock poisoned");
+                    None
+                }
+            },
+            None => {
+                log::warn!("Tried using chat cache on closed connection");
+                None
+            }
+        }
+    }
+
+    pub fn last_message_id(&mut self, account: &mut Account, sn: &str) -> Option<String> {
+        purple::Chat::find(account, sn).and_then(|mut chat| {
+            chat.as_blist_node()
+                .get_string(&blist_node::LAST_MESSAGE_ID)
+                .map(|id| id.to_string())
+        })
+    }
+
+    pub fn chat_left(&mut self, connection: &mut Connection, sn: &str) {
+        log::info!("chat left: {}", sn);
+        if let Some(mut chat) = purple::Chat::find(&mut connection.get_account(), sn) {
+            chat.remove_from_blist();
+        }
+        self.with_chat_cache(connection, |cache| cache.remove(sn));
+        if connection
+            .get_account()
+            .find_chat_conversation(sn)
+            .is_some()
+        {
+            connection.serv_got_chat_left(sn);
+        }
+    }
+
     fn group_chat_joined(
         &mut self,
         connection: &mut Connection,
@@ -707,6 +1800,15 @@ impl PurpleICQ {
             None => {
                 let mut conversation = connection.serv_got_joined_chat(&info.sn).unwrap();
                 conversation.set_title(&info.title);
+
+                // Restore what we knew about the chat before the conversation was closed. The
+                // history info isn't, as the new conversation doesn't display any message yet.
+                let cached = self
+                    .with_chat_cache(connection, |cache| cache.get(&info.sn).cloned())
+                    .flatten();
+                if let Some(chat_info) = cached.and_then(|cached| cached.chat_info) {
+                    self.load_chat_info(connection, &chat_info);
+                }
                 conversation
             }
         }
@@ -754,6 +1856,8 @@ impl PurpleICQ {
                 conversation.set_title(&info.title);
                 let mut chat_conversation = conversation.get_chat_data().unwrap();
                 unsafe { conversation.set_data(conv_data::CHAT_INFO, info.clone()) };
+                let cached = info.clone();
+                self.with_chat_cache(connection, |cache| cache.set_chat_info(&info.sn, cached));
 
                 chat_conversation.clear_users();
                 for member in &info.members {
@@ -761,7 +1865,11 @@ impl PurpleICQ {
                 }
 
                 if let Some(about) = &info.about {
-                    chat_conversation.set_topic("unknown", about);
+                    let author_sn =
+                        unsafe { conversation.get_data::<TopicChange>(conv_data::TOPIC_CHANGE) }
+                            .map(|change| change.author_sn.clone())
+                            .unwrap_or_else(|| "unknown".into());
+                    chat_conversation.set_topic(&author_sn, about);
                 }
             }
             None => {
@@ -769,6 +1877,142 @@ impl PurpleICQ {
             }
         }
     }
+
+    pub fn chat_topic_changed(
+        &mut self,
+        connection: &mut Connection,
+        sn: &str,
+        change: TopicChange,
+        about: &str,
+    ) {
+        match connection.get_account().find_chat_conversation(sn) {
+            Some(mut conversation) => {
+                // History can replay older changes, only keep the most recent one.
+                let is_newer =
+                    unsafe { conversation.get_data::<TopicChange>(conv_data::TOPIC_CHANGE) }
+                        .map(|current| current.time <= change.time)
+                        .unwrap_or(true);
+                if is_newer {
+                    let mut chat_conversation = conversation.get_chat_data().unwrap();
+                    chat_conversation.set_topic(&change.author_sn, about);
+                    unsafe { conversation.set_data(conv_data::TOPIC_CHANGE, change) };
+                }
+            }
+            None => {
+                log::warn!("Topic changed for no conversation");
+            }
+        }
+    }
+
+    pub fn write_chat_system_message(
+        &mut self,
+        connection: &mut Connection,
+        sn: &str,
+        message: &str,
+    ) {
+        match connection.get_account().find_chat_conversation(sn) {
+            Some(mut conversation) => {
+                conversation.get_chat_data().unwrap().write(
+                    "",
+                    message,
+                    PurpleMessageFlags::PURPLE_MESSAGE_SYSTEM,
+                    unix_timestamp(),
+                );
+            }
+            None => {
+                log::warn!("System message for no conversation: {}", message);
+            }
+        }
+    }
+}
+
+// The options come first, so that the rest of the line is the path, spaces included.
+fn parse_export_args(args: &str) -> Option<(PathBuf, ExportFormat, Option<i64>)> {
+    let mut format = ExportFormat::JsonLines;
+    let mut since = None;
+
+    let mut rest = args.trim();
+    while rest.starts_with("--") {
+        let (option, after_option) = split_word(rest);
+        let (value, after_value) = split_word(after_option);
+        match option {
+            "--format" => format = ExportFormat::from_name(value)?,
+            "--since" => since = Some(date::parse_date(value)?),
+            _ => return None,
+        }
+        rest = after_value;
+    }
+
+    if rest.is_empty() {
+        return None;
+    }
+    Some((PathBuf::from(rest), format, since))
+}
+
+// Split the first word off a string, both parts trimmed.
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
 }
 
 purple_prpl_plugin!(PurpleICQ);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_export_args_path_only() {
+        assert_eq!(
+            parse_export_args("history.jsonl"),
+            Some((
+                PathBuf::from("history.jsonl"),
+                ExportFormat::JsonLines,
+                None
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_export_args_path_with_spaces() {
+        assert_eq!(
+            parse_export_args("  /tmp/my chat history.csv "),
+            Some((
+                PathBuf::from("/tmp/my chat history.csv"),
+                ExportFormat::JsonLines,
+                None
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_export_args_options() {
+        assert_eq!(
+            parse_export_args("--format csv  --since 2020-01-02 out file.csv"),
+            Some((
+                PathBuf::from("out file.csv"),
+                ExportFormat::Csv,
+                Some(1_577_923_200)
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_export_args_invalid() {
+        assert_eq!(parse_export_args(""), None);
+        assert_eq!(parse_export_args("--format csv"), None);
+        assert_eq!(parse_export_args("--format xml out.xml"), None);
+        assert_eq!(parse_export_args("--since yesterday out.csv"), None);
+        assert_eq!(parse_export_args("--unknown value out.csv"), None);
+    }
+}
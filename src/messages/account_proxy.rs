@@ -1,7 +1,7 @@
 use super::{FdSender, SystemMessage};
 use crate::Handle;
 use async_std::channel;
-use purple::{account, Account};
+use purple::{account, Account, RequestField, RequestFieldGroup, RequestFields};
 
 pub struct AccountProxy<'a> {
     pub handle: Handle,
@@ -79,6 +79,77 @@ impl<'a> AccountProxy<'a> {
         rx.recv().await.ok().flatten()
     }
 
+    pub async fn request_action(
+        &mut self,
+        title: Option<String>,
+        primary: Option<String>,
+        secondary: Option<String>,
+        default_action: usize,
+        who: Option<String>,
+        actions: Vec<String>,
+    ) -> Option<usize> {
+        let (tx, rx) = channel::bounded(1);
+        self.exec_no_return(move |account| {
+            let actions = actions.iter().map(String::as_str).collect::<Vec<_>>();
+            account.request_action(
+                title.as_deref(),
+                primary.as_deref(),
+                secondary.as_deref(),
+                default_action,
+                who.as_deref(),
+                &actions,
+                move |action| {
+                    if let Err(error) = tx.try_send(action) {
+                        log::error!("Failed to send result: {:?}", error);
+                    }
+                },
+            )
+        })
+        .await;
+
+        rx.recv().await.ok()
+    }
+
+    /// Show a captcha image and ask for the text it shows.
+    pub async fn request_captcha(
+        &mut self,
+        title: String,
+        primary: String,
+        image: Vec<u8>,
+        ok_text: String,
+        cancel_text: String,
+    ) -> Option<String> {
+        const TEXT_FIELD_ID: &str = "captcha";
+        let (tx, rx) = channel::bounded(1);
+        self.exec_no_return(move |account| {
+            let mut group = RequestFieldGroup::new(None);
+            group.add_field(RequestField::image("captcha_image", "", &image));
+            group.add_field(RequestField::string(TEXT_FIELD_ID, "Text", None, false));
+            let mut fields = RequestFields::new();
+            fields.add_group(group);
+            account.request_fields(
+                Some(&title),
+                Some(&primary),
+                None,
+                fields,
+                &ok_text,
+                &cancel_text,
+                move |fields| {
+                    let text = fields
+                        .and_then(|fields| fields.get_string(TEXT_FIELD_ID))
+                        .map(|v| v.into_owned());
+                    if let Err(error) = tx.try_send(text) {
+                        log::error!("Failed to send result: {:?}", error);
+                    }
+                },
+                None,
+            )
+        })
+        .await;
+
+        rx.recv().await.ok().flatten()
+    }
+
     pub async fn is_disconnected(&mut self) -> bool {
         self.exec(move |account| account.is_disconnected())
             .await
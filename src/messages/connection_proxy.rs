@@ -1,7 +1,7 @@
 use super::{FdSender, SystemMessage};
 use crate::Handle;
 use async_std::channel;
-use purple::{Connection, PurpleConnectionError, PurpleConnectionState};
+use purple::{Connection, PurpleConnectionError, PurpleConnectionState, PurpleNotifyMsgType};
 
 pub struct ConnectionProxy<'a> {
     pub handle: Handle,
@@ -47,6 +47,19 @@ impl<'a> ConnectionProxy<'a> {
             .await
     }
 
+    pub async fn notify_message(
+        &mut self,
+        msg_type: PurpleNotifyMsgType,
+        title: String,
+        primary: String,
+        secondary: Option<String>,
+    ) {
+        self.exec_no_return(move |connection| {
+            connection.notify_message(msg_type, &title, &primary, secondary.as_deref())
+        })
+        .await
+    }
+
     pub async fn error_reason(&mut self, reason: PurpleConnectionError, description: String) {
         self.exec_no_return(move |connection| connection.error_reason(reason, &description))
             .await
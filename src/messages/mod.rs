@@ -1,9 +1,12 @@
 use self::account_proxy::AccountProxy;
 use self::connection_proxy::ConnectionProxy;
 use self::handle_proxy::HandleProxy;
+use crate::icq::export::ExportFormat;
+use crate::icq::history::HistoryLimit;
 use crate::{AccountDataBox, Handle, ProtocolData, PurpleICQ};
 use async_std::channel::{Receiver, Sender};
 use purple::{Account, Connection};
+use std::path::PathBuf;
 
 mod account_proxy;
 mod connection_proxy;
@@ -89,6 +92,75 @@ pub struct JoinChatMessageData {
     pub stamp: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct LogoutMessageData {
+    /// Whether to end the session on the server, rather than keep it to be resumed.
+    pub end_session: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaveChatMessageData {
+    pub sn: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModifyChatMessageData {
+    pub sn: String,
+    pub name: Option<String>,
+    pub about: Option<String>,
+    pub rules: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InviteToChatMessageData {
+    pub sn: String,
+    pub who: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchChatsMessageData {
+    /// An empty keyword browses the chat catalog.
+    pub keyword: String,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AddBuddyMessageData {
+    pub sn: String,
+    pub group: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoveBuddyMessageData {
+    pub sn: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AliasBuddyMessageData {
+    pub sn: String,
+    /// An empty alias resets the contact to its own name.
+    pub alias: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MoveBuddyMessageData {
+    pub sn: String,
+    pub old_group: String,
+    pub new_group: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetBlockedMessageData {
+    pub sn: String,
+    pub blocked: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetMutedMessageData {
+    pub sn: String,
+    pub muted: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct SendMsgMessageData {
     pub to_sn: String,
@@ -103,21 +175,86 @@ pub struct GetChatInfoMessageData {
 #[derive(Debug, Clone)]
 pub struct GetHistoryMessageData {
     pub sn: String,
-    pub from_msg_id: String,
-    pub count: i32,
+    pub from_msg_id: Option<String>,
+    pub limit: HistoryLimit,
+}
+
+#[derive(Debug, Clone)]
+pub struct FetchContextMessageData {
+    pub sn: String,
+    pub msg_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchMessagesMessageData {
+    /// The conversation the results are written to.
+    pub sn: String,
+    pub keyword: String,
+    pub all_chats: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportHistoryMessageData {
+    pub sn: String,
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    pub since: Option<i64>,
+}
+
+/// The actions of the account menu.
+#[derive(Debug, Clone, Copy)]
+pub enum AccountAction {
+    Resync,
+    ShowAccountInfo,
+    ForgetLogin,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccountActionMessageData {
+    pub action: AccountAction,
 }
 
 #[derive(Debug)]
 pub enum PurpleMessage {
     Login(AccountInfo),
+    Logout(LogoutMessage),
     JoinChat(JoinChatMessage),
+    LeaveChat(LeaveChatMessage),
+    ModifyChat(ModifyChatMessage),
+    InviteToChat(InviteToChatMessage),
+    SearchChats(SearchChatsMessage),
+    AddBuddy(AddBuddyMessage),
+    RemoveBuddy(RemoveBuddyMessage),
+    AliasBuddy(AliasBuddyMessage),
+    MoveBuddy(MoveBuddyMessage),
+    SetBlocked(SetBlockedMessage),
+    SetMuted(SetMutedMessage),
     SendMsg(SendMsgMessage),
     GetChatInfo(GetChatInfoMessage),
     GetHistory(GetHistoryMessage),
+    FetchContext(FetchContextMessage),
+    SearchMessages(SearchMessagesMessage),
+    ExportHistory(ExportHistoryMessage),
+    AccountAction(AccountActionMessage),
 }
 
 pub type JoinChatMessage = PurpleMessageWithHandle<JoinChatMessageData>;
+pub type LogoutMessage = PurpleMessageWithHandle<LogoutMessageData>;
+pub type LeaveChatMessage = PurpleMessageWithHandle<LeaveChatMessageData>;
+pub type ModifyChatMessage = PurpleMessageWithHandle<ModifyChatMessageData>;
+pub type InviteToChatMessage = PurpleMessageWithHandle<InviteToChatMessageData>;
+pub type SearchChatsMessage = PurpleMessageWithHandle<SearchChatsMessageData>;
+pub type AddBuddyMessage = PurpleMessageWithHandle<AddBuddyMessageData>;
+pub type RemoveBuddyMessage = PurpleMessageWithHandle<RemoveBuddyMessageData>;
+pub type AliasBuddyMessage = PurpleMessageWithHandle<AliasBuddyMessageData>;
+pub type MoveBuddyMessage = PurpleMessageWithHandle<MoveBuddyMessageData>;
+pub type SetBlockedMessage = PurpleMessageWithHandle<SetBlockedMessageData>;
+pub type SetMutedMessage = PurpleMessageWithHandle<SetMutedMessageData>;
 pub type GetHistoryMessage = PurpleMessageWithHandle<GetHistoryMessageData>;
+pub type FetchContextMessage = PurpleMessageWithHandle<FetchContextMessageData>;
+pub type SearchMessagesMessage = PurpleMessageWithHandle<SearchMessagesMessageData>;
+pub type ExportHistoryMessage = PurpleMessageWithHandle<ExportHistoryMessageData>;
+pub type AccountActionMessage = PurpleMessageWithHandle<AccountActionMessageData>;
 pub type SendMsgMessage = PurpleMessageWithHandle<SendMsgMessageData>;
 pub type GetChatInfoMessage = PurpleMessageWithHandle<GetChatInfoMessageData>;
 
@@ -130,12 +267,138 @@ impl PurpleMessage {
         })
     }
 
+    pub fn logout(handle: Handle, protocol_data: AccountDataBox, end_session: bool) -> Self {
+        Self::Logout(LogoutMessage {
+            handle,
+            protocol_data,
+            message_data: LogoutMessageData { end_session },
+        })
+    }
+
+    pub fn leave_chat(handle: Handle, protocol_data: AccountDataBox, sn: String) -> Self {
+        Self::LeaveChat(LeaveChatMessage {
+            handle,
+            protocol_data,
+            message_data: LeaveChatMessageData { sn },
+        })
+    }
+
+    pub fn modify_chat(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        message_data: ModifyChatMessageData,
+    ) -> Self {
+        Self::ModifyChat(ModifyChatMessage {
+            handle,
+            protocol_data,
+            message_data,
+        })
+    }
+
+    pub fn invite_to_chat(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        who: String,
+    ) -> Self {
+        Self::InviteToChat(InviteToChatMessage {
+            handle,
+            protocol_data,
+            message_data: InviteToChatMessageData { sn, who },
+        })
+    }
+
+    pub fn search_chats(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        keyword: String,
+        count: u32,
+    ) -> Self {
+        Self::SearchChats(SearchChatsMessage {
+            handle,
+            protocol_data,
+            message_data: SearchChatsMessageData { keyword, count },
+        })
+    }
+
+    pub fn add_buddy(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        group: String,
+    ) -> Self {
+        Self::AddBuddy(AddBuddyMessage {
+            handle,
+            protocol_data,
+            message_data: AddBuddyMessageData { sn, group },
+        })
+    }
+
+    pub fn remove_buddy(handle: Handle, protocol_data: AccountDataBox, sn: String) -> Self {
+        Self::RemoveBuddy(RemoveBuddyMessage {
+            handle,
+            protocol_data,
+            message_data: RemoveBuddyMessageData { sn },
+        })
+    }
+
+    pub fn alias_buddy(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        alias: String,
+    ) -> Self {
+        Self::AliasBuddy(AliasBuddyMessage {
+            handle,
+            protocol_data,
+            message_data: AliasBuddyMessageData { sn, alias },
+        })
+    }
+
+    pub fn move_buddy(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        message_data: MoveBuddyMessageData,
+    ) -> Self {
+        Self::MoveBuddy(MoveBuddyMessage {
+            handle,
+            protocol_data,
+            message_data,
+        })
+    }
+
+    pub fn set_blocked(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        blocked: bool,
+    ) -> Self {
+        Self::SetBlocked(SetBlockedMessage {
+            handle,
+            protocol_data,
+            message_data: SetBlockedMessageData { sn, blocked },
+        })
+    }
+
+    pub fn set_muted(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        muted: bool,
+    ) -> Self {
+        Self::SetMuted(SetMutedMessage {
+            handle,
+            protocol_data,
+            message_data: SetMutedMessageData { sn, muted },
+        })
+    }
+
     pub fn fetch_history(
         handle: Handle,
         protocol_data: AccountDataBox,
         sn: String,
-        from_msg_id: String,
-        count: i32,
+        from_msg_id: Option<String>,
+        limit: HistoryLimit,
     ) -> Self {
         Self::GetHistory(GetHistoryMessage {
             handle,
@@ -143,11 +406,60 @@ impl PurpleMessage {
             message_data: GetHistoryMessageData {
                 sn,
                 from_msg_id,
-                count,
+                limit,
             },
         })
     }
 
+    pub fn fetch_context(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        sn: String,
+        msg_id: String,
+    ) -> Self {
+        Self::FetchContext(FetchContextMessage {
+            handle,
+            protocol_data,
+            message_data: FetchContextMessageData { sn, msg_id },
+        })
+    }
+
+    pub fn search_messages(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        message_data: SearchMessagesMessageData,
+    ) -> Self {
+        Self::SearchMessages(SearchMessagesMessage {
+            handle,
+            protocol_data,
+            message_data,
+        })
+    }
+
+    pub fn export_history(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        message_data: ExportHistoryMessageData,
+    ) -> Self {
+        Self::ExportHistory(ExportHistoryMessage {
+            handle,
+            protocol_data,
+            message_data,
+        })
+    }
+
+    pub fn account_action(
+        handle: Handle,
+        protocol_data: AccountDataBox,
+        action: AccountAction,
+    ) -> Self {
+        Self::AccountAction(AccountActionMessage {
+            handle,
+            protocol_data,
+            message_data: AccountActionMessageData { action },
+        })
+    }
+
     pub fn send_msg(
         handle: Handle,
         protocol_data: AccountDataBox,